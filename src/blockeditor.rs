@@ -0,0 +1,314 @@
+use gtk::prelude::*;
+use gtk::{DrawingArea, Label};
+use cairo;
+use gdk;
+use gdk::prelude::*;
+use gdk_pixbuf::{InterpType, Pixbuf};
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::rc::Rc;
+
+use blockset::{Block, Blockset};
+use cgb::TileAttributes;
+use constants::*;
+use gfx::TileSheet;
+use maparea::Maparea;
+use palette::change_palette;
+use tileset::Tileset;
+
+/// `GDK_INTERP_NEAREST`, which gdk-pixbuf only exposes as a raw constant
+const INTERP_NEAREST: InterpType = 0;
+
+/// Zoom of the block being edited
+const BLOCK_SCALE: i32 = 4;
+/// Zoom of the tile sheet the tiles are picked from
+const SHEET_SCALE: i32 = 2;
+
+/// Side panel editing the tiles of the block selected in the tileset
+#[derive(Debug)]
+pub struct BlockEditor {
+    tileset: Rc<RefCell<Tileset>>,
+    maparea: Rc<RefCell<Option<Maparea>>>,
+    /// Index of the tile of the sheet that left clicks put into the block
+    tile: u8,
+    /// CGB attributes given along with `tile`
+    attributes: TileAttributes,
+    block_widget: DrawingArea,
+    sheet_widget: DrawingArea,
+    label: Label,
+}
+
+impl BlockEditor {
+    pub fn from_data(
+        block_widget: DrawingArea,
+        sheet_widget: DrawingArea,
+        label: Label,
+        tileset: Rc<RefCell<Tileset>>,
+        maparea: Rc<RefCell<Option<Maparea>>>,
+    ) -> Rc<RefCell<Self>> {
+        block_widget.add_events(drawing_area_mask_bits!());
+        sheet_widget.add_events(drawing_area_mask_bits!());
+
+        let editor = BlockEditor {
+            tileset: tileset,
+            maparea: maparea,
+            tile: 0,
+            attributes: TileAttributes::default(),
+            block_widget: block_widget,
+            sheet_widget: sheet_widget,
+            label: label,
+        };
+        editor.refresh();
+        let cell = Rc::new(RefCell::new(editor));
+
+        {
+            let editor = cell.borrow();
+
+            editor.block_widget.connect_draw(clone!(cell => move |_, context| {
+                cell.borrow().paint_block(&context);
+                Inhibit::default()
+            }));
+
+            editor.block_widget.connect_button_press_event(clone!(cell => move |_, ev| {
+                cell.borrow_mut().block_press(ev);
+                Inhibit::default()
+            }));
+
+            editor.sheet_widget.connect_draw(clone!(cell => move |_, context| {
+                cell.borrow().paint_sheet(&context);
+                Inhibit::default()
+            }));
+
+            editor.sheet_widget.connect_button_press_event(clone!(cell => move |_, ev| {
+                cell.borrow_mut().sheet_press(ev);
+                Inhibit::default()
+            }));
+        }
+
+        cell
+    }
+
+    /// Show the block now selected in the tileset, and the current tile sheet
+    pub fn refresh(&self) {
+        let tileset = self.tileset.borrow();
+        let sheet = tileset.sheet();
+        self.sheet_widget.set_size_request(
+            sheet.get_width() * SHEET_SCALE,
+            sheet.get_height() * SHEET_SCALE,
+        );
+
+        let block_size = BLOCK_SIZE as i32 * BLOCK_SCALE;
+        self.block_widget.set_size_request(block_size, block_size);
+
+        self.label.set_label(&match tileset.selected {
+            Some(block) => format!(
+                "Block {:#04x} of {}",
+                block,
+                tileset.blockset().len()
+            ),
+            None => "No block selected".to_string(),
+        });
+
+        self.block_widget.queue_draw();
+        self.sheet_widget.queue_draw();
+    }
+
+    /// Tile chosen in the sheet
+    pub fn tile(&self) -> u8 {
+        self.tile
+    }
+
+    pub fn attributes(&self) -> TileAttributes {
+        self.attributes
+    }
+
+    pub fn set_attributes(&mut self, attributes: TileAttributes) {
+        self.attributes = attributes;
+    }
+
+    /// Append a copy of the selected block and select it
+    pub fn add_block(&mut self) -> Result<(), String> {
+        let block = {
+            let tileset = self.tileset.borrow();
+            tileset
+                .selected
+                .and_then(|id| tileset.blockset().get(id).cloned())
+                .unwrap_or_else(|| Block::new([0; TILES_IN_BLOCK]))
+        };
+
+        let id = self.edit_blockset(|blockset| blockset.push(block))
+            .ok_or("The blockset already has 256 blocks")?;
+        self.tileset.borrow_mut().select_tile_at(id);
+        self.refresh();
+        Ok(())
+    }
+
+    /// Remove the last block, unless the open map still uses it
+    pub fn remove_last_block(&mut self) -> Result<(), String> {
+        let last = {
+            let tileset = self.tileset.borrow();
+            match tileset.blockset().len() {
+                0 | 1 => return Err("The blockset needs at least one block".to_string()),
+                len => (len - 1) as u8,
+            }
+        };
+
+        let used = self.maparea
+            .borrow()
+            .as_ref()
+            .map_or(false, |maparea| maparea.map().as_bytes().contains(&last));
+        if used {
+            return Err(format!("Block {:#04x} is used by the map", last));
+        }
+
+        self.edit_blockset(|blockset| blockset.pop());
+        self.refresh();
+        Ok(())
+    }
+
+    /// Change the blockset, as an undoable step of the map when there is one
+    fn edit_blockset<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut Blockset) -> R,
+    {
+        match *self.maparea.borrow_mut() {
+            Some(ref mut maparea) => maparea.edit_blockset(f),
+            None => self.tileset.borrow_mut().edit_blockset(f),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let bytes = self.tileset.borrow().blockset().to_bytes();
+        File::create(path)?.write_all(&bytes)
+    }
+
+    pub fn save_attributes(&self, path: &str) -> io::Result<()> {
+        let bytes = self.tileset.borrow().blockset().attributes_to_bytes();
+        File::create(path)?.write_all(&bytes)
+    }
+
+    fn paint_block(&self, context: &cairo::Context) {
+        let tileset = self.tileset.borrow();
+        let block = match tileset.selected {
+            Some(block) => block,
+            None => return,
+        };
+
+        let size = BLOCK_SIZE as i32 * BLOCK_SCALE;
+        let pix = tileset.get_tile_pix(block)
+            .and_then(|tile| tile.scale_simple(size, size, INTERP_NEAREST).ok());
+        if let Some(pix) = pix {
+            context.set_source_pixbuf(&pix, 0., 0.);
+            context.paint();
+        }
+
+        /* slot borders */
+        let slot = (TILE_SIZE as i32 * BLOCK_SCALE) as f64;
+        context.set_source_rgba(0.5, 0.5, 0.5, 0.8);
+        context.set_line_width(1.);
+        for i in 1..TILES_IN_ROW {
+            let offset = i as f64 * slot + 0.5;
+            context.move_to(offset, 0.);
+            context.line_to(offset, size as f64);
+            context.move_to(0., offset);
+            context.line_to(size as f64, offset);
+        }
+        context.stroke();
+    }
+
+    fn paint_sheet(&self, context: &cairo::Context) {
+        let tileset = self.tileset.borrow();
+        let sheet = tileset.sheet();
+        let (width, height) = (sheet.get_width() * SHEET_SCALE, sheet.get_height() * SHEET_SCALE);
+        let pix: Pixbuf = match sheet.scale_simple(width, height, INTERP_NEAREST) {
+            Ok(pix) => pix,
+            Err(()) => return,
+        };
+        context.set_source_pixbuf(&pix, 0., 0.);
+        context.paint();
+
+        let (x, y) = self.sheet_coords(self.tile);
+        let size = TILE_SIZE as i32 * SHEET_SCALE;
+        if x + size <= width && y + size <= height {
+            let subpix = pix.new_subpixbuf(x, y, size, size);
+            let selected_subpix = change_palette(&subpix, tileset.palette(), SELECT_PALETTE);
+            context.set_source_pixbuf(&selected_subpix, x as f64, y as f64);
+            context.paint();
+        }
+    }
+
+    fn sheet_columns(&self) -> i32 {
+        self.tileset.borrow().sheet().get_width() / TILE_SIZE as i32
+    }
+
+    fn sheet_coords(&self, tile: u8) -> (i32, i32) {
+        let columns = self.sheet_columns().max(1);
+        let size = TILE_SIZE as i32 * SHEET_SCALE;
+        ((tile as i32 % columns) * size, (tile as i32 / columns) * size)
+    }
+
+    fn sheet_press(&mut self, ev: &gdk::EventButton) {
+        let (x, y) = ev.get_position();
+        let size = (TILE_SIZE as i32 * SHEET_SCALE) as f64;
+        let (column, row) = ((x / size) as usize, (y / size) as usize);
+        let columns = self.sheet_columns() as usize;
+        let tile = column + row * columns;
+
+        let in_bank = TileSheet::bank_and_id(tile).is_some();
+        if column < columns && tile < self.tileset.borrow().tiles().len() && in_bank {
+            self.tile = tile as u8;
+            self.sheet_widget.queue_draw();
+        }
+    }
+
+    /// Left click puts the chosen tile and attributes in a slot, right click picks them from a slot
+    fn block_press(&mut self, ev: &gdk::EventButton) {
+        let (x, y) = ev.get_position();
+        let size = (TILE_SIZE as i32 * BLOCK_SCALE) as f64;
+        let (slot_x, slot_y) = ((x / size) as usize, (y / size) as usize);
+        if slot_x >= TILES_IN_ROW || slot_y >= TILES_IN_ROW {
+            return;
+        }
+
+        let selected = self.tileset.borrow().selected;
+        let id = match selected {
+            Some(id) => id,
+            None => return,
+        };
+
+        match ev.as_ref().button {
+            1 => {
+                /* the bank comes with the tile, as the games load tilesets */
+                let (bank, tile) = match TileSheet::bank_and_id(self.tile as usize) {
+                    Some(bank_and_id) => bank_and_id,
+                    None => return,
+                };
+                let attributes = TileAttributes { bank: bank, ..self.attributes };
+                self.edit_blockset(|blockset| {
+                    blockset.get_mut(id).map(|block| {
+                        block.set_tile(slot_x, slot_y, tile);
+                        block.set_attributes(slot_x, slot_y, attributes);
+                    });
+                });
+                self.block_widget.queue_draw();
+            }
+            3 => {
+                let picked = self.tileset.borrow().blockset().get(id).map(|block| {
+                    (block.tile(slot_x, slot_y), block.attributes(slot_x, slot_y))
+                });
+                if let Some((tile, attributes)) = picked {
+                    /* tiles missing from the tileset keep the current one */
+                    if let Some(index) = TileSheet::index(attributes.bank, tile) {
+                        self.tile = index as u8;
+                    }
+                    self.attributes = attributes;
+                    self.sheet_widget.queue_draw();
+                }
+            }
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,386 @@
+use std::cmp::max;
+use std::error::Error;
+use std::fmt;
+
+use cgb::TileAttributes;
+use constants::*;
+
+/// A block: `TILES_IN_ROW` x `TILES_IN_ROW` tile ids, stored row by row,
+/// and the CGB attributes of each of them
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Block {
+    tiles: [u8; TILES_IN_BLOCK],
+    attributes: [TileAttributes; TILES_IN_BLOCK],
+}
+
+impl Block {
+    pub fn new(tiles: [u8; TILES_IN_BLOCK]) -> Self {
+        Block {
+            tiles: tiles,
+            attributes: [TileAttributes::default(); TILES_IN_BLOCK],
+        }
+    }
+
+    pub fn tile(&self, x: usize, y: usize) -> u8 {
+        self.tiles[x + y * TILES_IN_ROW]
+    }
+
+    pub fn set_tile(&mut self, x: usize, y: usize, tile: u8) {
+        self.tiles[x + y * TILES_IN_ROW] = tile;
+    }
+
+    pub fn tiles(&self) -> &[u8] {
+        &self.tiles
+    }
+
+    pub fn attributes(&self, x: usize, y: usize) -> TileAttributes {
+        self.attributes[x + y * TILES_IN_ROW]
+    }
+
+    pub fn set_attributes(&mut self, x: usize, y: usize, attributes: TileAttributes) {
+        self.attributes[x + y * TILES_IN_ROW] = attributes;
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum BlocksetError {
+    Truncated { len: usize },
+    TooManyBlocks { count: usize },
+    TileOutOfRange {
+        block: usize,
+        x: usize,
+        y: usize,
+        tile: u8,
+        tile_count: usize,
+    },
+    AttributesLength { len: usize, expected: usize },
+}
+
+impl fmt::Display for BlocksetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BlocksetError::Truncated { len } => write!(
+                f,
+                "blockset is {} bytes long, which is not a multiple of {}",
+                len,
+                TILES_IN_BLOCK
+            ),
+            BlocksetError::TooManyBlocks { count } => {
+                write!(f, "blockset has {} blocks, at most 256 are supported", count)
+            }
+            BlocksetError::TileOutOfRange {
+                block,
+                x,
+                y,
+                tile,
+                tile_count,
+            } => write!(
+                f,
+                "block {} uses tile {} at ({}, {}), but the tileset only has {} tiles",
+                block,
+                tile,
+                x,
+                y,
+                tile_count
+            ),
+            BlocksetError::AttributesLength { len, expected } => write!(
+                f,
+                "attributes are {} bytes long, but the blockset needs {}",
+                len,
+                expected
+            ),
+        }
+    }
+}
+
+impl Error for BlocksetError {}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Blockset {
+    blocks: Vec<Block>,
+}
+
+impl Blockset {
+    /// Parse a blockset file, checking every tile id against `tile_count`
+    pub fn from_bytes(bytes: &[u8], tile_count: usize) -> Result<Self, BlocksetError> {
+        if !bytes.len().is_multiple_of(TILES_IN_BLOCK) {
+            return Err(BlocksetError::Truncated { len: bytes.len() });
+        }
+
+        let count = bytes.len() / TILES_IN_BLOCK;
+        if count > 256 {
+            return Err(BlocksetError::TooManyBlocks { count: count });
+        }
+
+        let mut blocks = Vec::with_capacity(count);
+        for (i, chunk) in bytes.chunks(TILES_IN_BLOCK).enumerate() {
+            let mut tiles = [0; TILES_IN_BLOCK];
+            for (slot, &tile) in chunk.iter().enumerate() {
+                if tile as usize >= tile_count {
+                    return Err(BlocksetError::TileOutOfRange {
+                        block: i,
+                        x: slot % TILES_IN_ROW,
+                        y: slot / TILES_IN_ROW,
+                        tile: tile,
+                        tile_count: tile_count,
+                    });
+                }
+                tiles[slot] = tile;
+            }
+            blocks.push(Block::new(tiles));
+        }
+
+        Ok(Blockset { blocks: blocks })
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn get(&self, id: u8) -> Option<&Block> {
+        self.blocks.get(id as usize)
+    }
+
+    pub fn get_mut(&mut self, id: u8) -> Option<&mut Block> {
+        self.blocks.get_mut(id as usize)
+    }
+
+    /// Append a block, returning its id, unless all 256 ids are taken
+    pub fn push(&mut self, block: Block) -> Option<u8> {
+        if self.blocks.len() >= 256 {
+            return None;
+        }
+        self.blocks.push(block);
+        Some((self.blocks.len() - 1) as u8)
+    }
+
+    /// Remove the last block. Blocks in the middle can't go, since maps
+    /// refer to the ones after them by id.
+    pub fn pop(&mut self) -> Option<Block> {
+        self.blocks.pop()
+    }
+
+    pub fn iter(&self) -> ::std::slice::Iter<'_, Block> {
+        self.blocks.iter()
+    }
+
+    /// Ids of the blocks showing tile `tile` of VRAM bank `bank`
+    pub fn blocks_using(&self, bank: u8, tile: u8) -> Vec<u8> {
+        self.blocks
+            .iter()
+            .enumerate()
+            .filter(|&(_, block)| {
+                block
+                    .tiles
+                    .iter()
+                    .zip(block.attributes.iter())
+                    .any(|(&id, attributes)| id == tile && attributes.bank == bank)
+            })
+            .map(|(id, _)| id as u8)
+            .collect()
+    }
+
+    /// Blocks that differ in `other`, by id, `None` for those `other` lacks
+    pub fn changes_to(&self, other: &Blockset) -> Vec<(u8, Option<Block>)> {
+        (0..max(self.blocks.len(), other.blocks.len()))
+            .filter(|&id| self.blocks.get(id) != other.blocks.get(id))
+            .map(|id| (id as u8, other.blocks.get(id).cloned()))
+            .collect()
+    }
+
+    /// Turn this blockset into the one `changes` were taken to by `changes_to`
+    pub fn apply_changes(&mut self, changes: &[(u8, Option<Block>)]) {
+        for &(id, block) in changes {
+            match block {
+                Some(block) if (id as usize) < self.blocks.len() => self.blocks[id as usize] = block,
+                Some(block) => self.blocks.push(block),
+                None => (),
+            }
+        }
+        if let Some(&(id, _)) = changes.iter().find(|&&(_, block)| block.is_none()) {
+            self.blocks.truncate(id as usize);
+        }
+    }
+
+    /// Bytes of the blockset file, the inverse of `from_bytes`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.blocks
+            .iter()
+            .flat_map(|block| block.tiles().iter().cloned())
+            .collect()
+    }
+
+    /// Read the CGB attributes of every tile, one byte each, laid out like
+    /// the blockset file
+    pub fn load_attributes(&mut self, bytes: &[u8]) -> Result<(), BlocksetError> {
+        let expected = self.blocks.len() * TILES_IN_BLOCK;
+        if bytes.len() != expected {
+            return Err(BlocksetError::AttributesLength {
+                len: bytes.len(),
+                expected: expected,
+            });
+        }
+
+        for (block, chunk) in self.blocks.iter_mut().zip(bytes.chunks(TILES_IN_BLOCK)) {
+            for (attributes, &byte) in block.attributes.iter_mut().zip(chunk) {
+                *attributes = TileAttributes::from_byte(byte);
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse pokecrystal's metatiles.bin and attributes.bin. Tile ids aren't
+    /// checked, since metatiles also use tiles loaded apart from the tileset.
+    pub fn from_metatiles(metatiles: &[u8], attributes: &[u8]) -> Result<Self, BlocksetError> {
+        let mut blockset = Self::from_bytes(metatiles, 256)?;
+        blockset.load_attributes(attributes)?;
+        Ok(blockset)
+    }
+
+    /// Bytes of the attributes file, the inverse of `load_attributes`
+    pub fn attributes_to_bytes(&self) -> Vec<u8> {
+        self.blocks
+            .iter()
+            .flat_map(|block| block.attributes.iter().map(TileAttributes::to_byte))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_blocks_row_by_row() {
+        let bytes: Vec<u8> = (0..32).collect();
+        let blockset = Blockset::from_bytes(&bytes, 32).unwrap();
+
+        assert_eq!(blockset.len(), 2);
+        assert_eq!(blockset.get(0).unwrap().tile(1, 2), 9);
+        assert_eq!(blockset.get(1).unwrap().tile(3, 3), 31);
+        assert!(blockset.get(2).is_none());
+        assert_eq!(blockset.blocks_using(0, 17), vec![1]);
+    }
+
+    #[test]
+    fn blocks_using_matches_the_bank() {
+        let bytes: Vec<u8> = (0..32).map(|i| i % 4).collect();
+        let mut blockset = Blockset::from_bytes(&bytes, 4).unwrap();
+        let bank_1 = TileAttributes {
+            bank: 1,
+            ..TileAttributes::default()
+        };
+        blockset.get_mut(1).unwrap().set_attributes(2, 3, bank_1);
+
+        assert_eq!(blockset.blocks_using(0, 2), vec![0, 1]);
+        assert_eq!(blockset.blocks_using(1, 2), vec![1]);
+        assert!(blockset.blocks_using(1, 3).is_empty());
+    }
+
+    #[test]
+    fn edits_survive_a_round_trip() {
+        let bytes: Vec<u8> = (0..32).collect();
+        let mut blockset = Blockset::from_bytes(&bytes, 32).unwrap();
+        blockset.get_mut(1).unwrap().set_tile(2, 0, 5);
+        assert_eq!(blockset.push(Block::new([7; TILES_IN_BLOCK])), Some(2));
+
+        let saved = blockset.to_bytes();
+        assert_eq!(saved.len(), 48);
+        assert_eq!(saved[16 + 2], 5);
+        assert_eq!(Blockset::from_bytes(&saved, 32).unwrap(), blockset);
+
+        assert_eq!(blockset.pop(), Some(Block::new([7; TILES_IN_BLOCK])));
+        assert_eq!(blockset.len(), 2);
+    }
+
+    #[test]
+    fn attributes_survive_a_round_trip() {
+        let mut blockset = Blockset::from_bytes(&[0; 32], 1).unwrap();
+        let mut attributes = vec![0; 32];
+        attributes[16 + 5] = 0x23;
+        blockset.load_attributes(&attributes).unwrap();
+
+        let loaded = blockset.get(1).unwrap().attributes(1, 1);
+        assert_eq!(loaded.palette, 3);
+        assert!(loaded.x_flip);
+        assert_eq!(blockset.attributes_to_bytes(), attributes);
+        assert_eq!(
+            blockset.load_attributes(&[0; 16]),
+            Err(BlocksetError::AttributesLength {
+                len: 16,
+                expected: 32,
+            })
+        );
+    }
+
+    #[test]
+    fn loads_metatiles() {
+        let mut metatiles = vec![0x7f; 32];
+        metatiles[3] = 0x60;
+        let attributes: Vec<u8> = (0..32).map(|i| i & 0x0f | 0x20).collect();
+        let blockset = Blockset::from_metatiles(&metatiles, &attributes).unwrap();
+
+        assert_eq!(blockset.get(0).unwrap().tile(3, 0), 0x60);
+        assert_eq!(blockset.get(1).unwrap().attributes(2, 3).to_byte(), 0x2e);
+        assert_eq!(blockset.to_bytes(), metatiles);
+        assert_eq!(blockset.attributes_to_bytes(), attributes);
+        assert_eq!(
+            Blockset::from_metatiles(&metatiles, &attributes[16..]),
+            Err(BlocksetError::AttributesLength {
+                len: 16,
+                expected: 32,
+            })
+        );
+    }
+
+    #[test]
+    fn holds_at_most_256_blocks() {
+        let mut blockset = Blockset::from_bytes(&vec![0; 256 * TILES_IN_BLOCK], 1).unwrap();
+        assert_eq!(blockset.push(Block::new([0; TILES_IN_BLOCK])), None);
+        assert_eq!(blockset.len(), 256);
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        assert_eq!(
+            Blockset::from_bytes(&[0; 20], 1),
+            Err(BlocksetError::Truncated { len: 20 })
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_tiles() {
+        let mut bytes = vec![0; 32];
+        bytes[16 + 6] = 4;
+        assert_eq!(
+            Blockset::from_bytes(&bytes, 4),
+            Err(BlocksetError::TileOutOfRange {
+                block: 1,
+                x: 2,
+                y: 1,
+                tile: 4,
+                tile_count: 4,
+            })
+        );
+    }
+
+    #[test]
+    fn changes_turn_one_blockset_into_another() {
+        let before = Blockset::from_bytes(&[1; 48], 2).unwrap();
+        let mut after = before.clone();
+        after.get_mut(1).unwrap().set_tile(0, 0, 0);
+        after.pop();
+        after.pop();
+        after.push(Block::new([0; TILES_IN_BLOCK]));
+
+        let mut blockset = before.clone();
+        blockset.apply_changes(&before.changes_to(&after));
+        assert_eq!(blockset, after);
+        blockset.apply_changes(&after.changes_to(&before));
+        assert_eq!(blockset, before);
+    }
+}
@@ -0,0 +1,184 @@
+use std::error::Error;
+use std::fmt;
+
+use constants::*;
+
+/// Number of background palettes on the Game Boy Color
+pub const BG_PALETTES: usize = 8;
+/// Size of the background palettes as stored in a palette file
+pub const BG_PALETTES_BYTES: usize = BG_PALETTES * 4 * 2;
+
+/// Attributes of a background tile on the Game Boy Color, as held in VRAM bank 1
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TileAttributes {
+    /// Background palette, 0 to 7
+    pub palette: u8,
+    /// VRAM bank the tile data is read from, 0 or 1
+    pub bank: u8,
+    pub x_flip: bool,
+    pub y_flip: bool,
+    /// Draw the tile over sprites
+    pub priority: bool,
+}
+
+impl TileAttributes {
+    /// Decode an attribute byte: palette in bits 0-2, bank in bit 3, flips in
+    /// bits 5 and 6, priority in bit 7
+    pub fn from_byte(byte: u8) -> Self {
+        TileAttributes {
+            palette: byte & 0b111,
+            bank: (byte >> 3) & 1,
+            x_flip: byte & 0x20 != 0,
+            y_flip: byte & 0x40 != 0,
+            priority: byte & 0x80 != 0,
+        }
+    }
+
+    pub fn to_byte(&self) -> u8 {
+        let mut byte = (self.palette & 0b111) | (self.bank & 1) << 3;
+        if self.x_flip {
+            byte |= 0x20;
+        }
+        if self.y_flip {
+            byte |= 0x40;
+        }
+        if self.priority {
+            byte |= 0x80;
+        }
+        byte
+    }
+}
+
+/// Expand a 15 bit color, red in the lowest 5 bits, to 8 bits per channel
+pub fn rgb_from_555(color: u16) -> RgbTriple {
+    let channel = |shift: u16| {
+        let value = ((color >> shift) & 0x1f) as u8;
+        (value << 3) | (value >> 2)
+    };
+    (channel(0), channel(5), channel(10))
+}
+
+/// Nearest 15 bit color to `rgb`
+pub fn rgb555_from((red, green, blue): RgbTriple) -> u16 {
+    let channel = |value: u8| (value as u16 * 31 + 127) / 255;
+    channel(red) | (channel(green) << 5) | (channel(blue) << 10)
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum CgbError {
+    PalettesLength { len: usize },
+}
+
+impl fmt::Display for CgbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CgbError::PalettesLength { len } => write!(
+                f,
+                "palette file is {} bytes long, but {} background palettes take {}",
+                len,
+                BG_PALETTES,
+                BG_PALETTES_BYTES
+            ),
+        }
+    }
+}
+
+impl Error for CgbError {}
+
+/// The eight background palettes, four 15 bit colors each, color 0 first
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BgPalettes {
+    pub colors: [[u16; 4]; BG_PALETTES],
+}
+
+impl Default for BgPalettes {
+    /// Every palette going from white to black
+    fn default() -> Self {
+        BgPalettes {
+            colors: [[0x7fff, 0x56b5, 0x294a, 0x0000]; BG_PALETTES],
+        }
+    }
+}
+
+impl BgPalettes {
+    /// Parse palettes as the CGB palette registers take them: little endian
+    /// colors, palette 0 first
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CgbError> {
+        if bytes.len() != BG_PALETTES_BYTES {
+            return Err(CgbError::PalettesLength { len: bytes.len() });
+        }
+
+        let mut palettes = BgPalettes::default();
+        for (i, pair) in bytes.chunks(2).enumerate() {
+            palettes.colors[i / 4][i % 4] = pair[0] as u16 | (pair[1] as u16) << 8;
+        }
+        Ok(palettes)
+    }
+
+    /// Bytes of the palette file, the inverse of `from_bytes`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.colors
+            .iter()
+            .flat_map(|palette| palette.iter())
+            .flat_map(|&color| vec![color as u8, (color >> 8) as u8])
+            .collect()
+    }
+
+    /// Palette `index` for rendering, its color 0 being shade 0
+    pub fn rgb_palette(&self, index: u8) -> RgbPalette {
+        let colors = self.colors[index as usize % BG_PALETTES];
+        RgbPalette::from_shades([
+            rgb_from_555(colors[0]),
+            rgb_from_555(colors[1]),
+            rgb_from_555(colors[2]),
+            rgb_from_555(colors[3]),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn attributes_round_trip() {
+        let attributes = TileAttributes::from_byte(0xad);
+        assert_eq!(
+            attributes,
+            TileAttributes {
+                palette: 5,
+                bank: 1,
+                x_flip: true,
+                y_flip: false,
+                priority: true,
+            }
+        );
+        assert_eq!(attributes.to_byte(), 0xad);
+        assert_eq!(TileAttributes::default().to_byte(), 0);
+    }
+
+    #[test]
+    fn converts_rgb555() {
+        assert_eq!(rgb_from_555(0x7fff), (255, 255, 255));
+        assert_eq!(rgb_from_555(0x001f), (255, 0, 0));
+        assert_eq!(rgb_from_555(0x7c00), (0, 0, 255));
+        assert_eq!(rgb555_from((255, 255, 255)), 0x7fff);
+        assert_eq!(rgb555_from(rgb_from_555(0x294a)), 0x294a);
+    }
+
+    #[test]
+    fn palettes_round_trip() {
+        let mut bytes = vec![0; BG_PALETTES_BYTES];
+        bytes[2 * 5] = 0x1f;
+        bytes[2 * 5 + 1] = 0x7c;
+        let palettes = BgPalettes::from_bytes(&bytes).unwrap();
+
+        assert_eq!(palettes.colors[1][1], 0x7c1f);
+        assert_eq!(palettes.rgb_palette(1).shade(1), (255, 0, 255));
+        assert_eq!(palettes.to_bytes(), bytes);
+        assert_eq!(
+            BgPalettes::from_bytes(&bytes[2..]),
+            Err(CgbError::PalettesLength { len: 62 })
+        );
+    }
+}
@@ -0,0 +1,173 @@
+use std::error::Error;
+use std::fmt;
+
+use blockset::Blockset;
+use map::Map;
+
+/// Collision bytes of pokecrystal collision files
+pub const COLL_FLOOR: u8 = 0x00;
+pub const COLL_WALL: u8 = 0x07;
+pub const COLL_CUT_TREE: u8 = 0x12;
+pub const COLL_HEADBUTT_TREE: u8 = 0x15;
+pub const COLL_TALL_GRASS: u8 = 0x18;
+pub const COLL_CUT_TREE_1A: u8 = 0x1a;
+pub const COLL_HEADBUTT_TREE_1D: u8 = 0x1d;
+pub const COLL_ICE: u8 = 0x23;
+pub const COLL_BUOY: u8 = 0x27;
+pub const COLL_WATER: u8 = 0x29;
+pub const COLL_ICE_2B: u8 = 0x2b;
+pub const COLL_COUNTER: u8 = 0x90;
+
+/// What the player needs to get onto a collision byte
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Permission {
+    Land,
+    /// Surf
+    Water,
+    /// Never, though some walls are talked to or cut down
+    Wall,
+}
+
+impl Permission {
+    /// Classify a collision byte like pokecrystal's `TileCollisionTable`
+    pub fn of(collision: u8) -> Self {
+        match collision {
+            COLL_ICE | COLL_ICE_2B => Permission::Land,
+            COLL_BUOY |
+            COLL_CUT_TREE |
+            COLL_CUT_TREE_1A |
+            COLL_HEADBUTT_TREE |
+            COLL_HEADBUTT_TREE_1D => Permission::Wall,
+            /* floors, grass, warps, ledges and one way walls */
+            0x00..=0x06 | 0x10..=0x1f | 0x40..=0x4f | 0x60..=0x7f | 0xa0..=0xff => Permission::Land,
+            /* water, whirlpools, waterfalls and currents */
+            0x20..=0x3f | 0x50..=0x5f => Permission::Water,
+            /* walls, counters, shelves and the like */
+            _ => Permission::Wall,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum CollisionError {
+    QuadrantsLength { len: usize, expected: usize },
+}
+
+impl fmt::Display for CollisionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CollisionError::QuadrantsLength { len, expected } => write!(
+                f,
+                "collision file is {} bytes long, but the blockset needs {}",
+                len,
+                expected
+            ),
+        }
+    }
+}
+
+impl Error for CollisionError {}
+
+/// Which steps of a map the player can walk on
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Collision {
+    /// Tiles the player can stand on, as in pokered
+    Passable(Vec<u8>),
+    /// Collision byte of each 16x16 quadrant of each block, as in pokecrystal
+    Quadrants(Vec<u8>),
+}
+
+impl Collision {
+    /// Parse a pokered list of passable tiles, which ends at `0xff`
+    pub fn from_passable_tiles(bytes: &[u8]) -> Self {
+        Collision::Passable(bytes.iter().cloned().take_while(|&tile| tile != 0xff).collect())
+    }
+
+    /// Parse pokecrystal collisions: four bytes per block, row by row
+    pub fn from_quadrants(bytes: &[u8], blocks: usize) -> Result<Self, CollisionError> {
+        if bytes.len() != blocks * 4 {
+            return Err(CollisionError::QuadrantsLength {
+                len: bytes.len(),
+                expected: blocks * 4,
+            });
+        }
+        Ok(Collision::Quadrants(bytes.to_vec()))
+    }
+
+    /// Whether the player can stand on step `(x, y)` of `map`, a step being a
+    /// quarter of a block; steps off the map are never walkable
+    pub fn is_walkable(&self, map: &Map, blockset: &Blockset, x: usize, y: usize) -> bool {
+        if x / 2 >= map.width() as usize || y / 2 >= map.height() as usize {
+            return false;
+        }
+        let block = match map.get((x / 2) as u8, (y / 2) as u8) {
+            Some(block) => block,
+            None => return false,
+        };
+        let (quadrant_x, quadrant_y) = (x % 2, y % 2);
+
+        match *self {
+            /* the games only look at the bottom left tile of the step */
+            Collision::Passable(ref tiles) => blockset
+                .get(block)
+                .is_some_and(|block| tiles.contains(&block.tile(quadrant_x * 2, quadrant_y * 2 + 1))),
+            /* walking only gets onto land: water needs Surf, and walls stop everyone */
+            Collision::Quadrants(ref quadrants) => quadrants
+                .get(block as usize * 4 + quadrant_x + quadrant_y * 2)
+                .is_some_and(|&collision| Permission::of(collision) == Permission::Land),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use blockset::Block;
+    use constants::*;
+
+    fn blockset() -> Blockset {
+        let mut blockset = Blockset::default();
+        let mut tiles = [0; TILES_IN_BLOCK];
+        /* bottom left tile of the top right step */
+        tiles[2 + TILES_IN_ROW] = 5;
+        blockset.push(Block::new(tiles));
+        blockset
+    }
+
+    #[test]
+    fn checks_passable_tiles() {
+        let collision = Collision::from_passable_tiles(&[0x05, 0xff, 0x00]);
+        let map = Map::new(1, 1, 0);
+
+        assert_eq!(collision, Collision::Passable(vec![5]));
+        assert!(collision.is_walkable(&map, &blockset(), 1, 0));
+        assert!(!collision.is_walkable(&map, &blockset(), 0, 0));
+        assert!(!collision.is_walkable(&map, &blockset(), 2, 0));
+    }
+
+    #[test]
+    fn checks_quadrants() {
+        let quadrants = [
+            COLL_FLOOR, COLL_WALL, COLL_TALL_GRASS, COLL_FLOOR,
+            COLL_WATER, COLL_COUNTER, COLL_ICE, COLL_HEADBUTT_TREE,
+        ];
+        let collision = Collision::from_quadrants(&quadrants, 2).unwrap();
+        let map = Map::from_bytes(2, 1, vec![0, 1]).unwrap();
+
+        assert!(collision.is_walkable(&map, &blockset(), 0, 0));
+        assert!(!collision.is_walkable(&map, &blockset(), 1, 0));
+        assert!(collision.is_walkable(&map, &blockset(), 0, 1));
+        assert!(!collision.is_walkable(&map, &blockset(), 0, 2));
+        assert!(!collision.is_walkable(&map, &blockset(), 2, 0));
+        assert!(!collision.is_walkable(&map, &blockset(), 3, 0));
+        assert!(collision.is_walkable(&map, &blockset(), 2, 1));
+        assert!(!collision.is_walkable(&map, &blockset(), 3, 1));
+        assert_eq!(Permission::of(COLL_WATER), Permission::Water);
+        assert_eq!(Permission::of(COLL_COUNTER), Permission::Wall);
+        assert_eq!(
+            Collision::from_quadrants(&[0; 6], 1),
+            Err(CollisionError::QuadrantsLength { len: 6, expected: 4 })
+        );
+    }
+}
@@ -1,8 +1,50 @@
 use std::path::PathBuf;
 
+use constants::*;
+use history::Limit;
+
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Config {
     pub recent: Option<RecentSettings>,
+    pub display: Option<DisplaySettings>,
+    pub overlays: Option<OverlaySettings>,
+    pub history: Option<HistorySettings>,
+}
+
+impl Config {
+    /// Palette the map and tiles are shown with
+    pub fn display_palette(&self) -> RgbPalette {
+        self.display.as_ref().map_or(BASE_PALETTE, |display| display.palette())
+    }
+
+    pub fn overlay_colors(&self) -> OverlayColors {
+        let defaults = OverlayColors::default();
+        match self.overlays {
+            Some(ref overlays) => OverlayColors {
+                block_grid: overlays.block_grid_color.unwrap_or(defaults.block_grid),
+                tile_grid: overlays.tile_grid_color.unwrap_or(defaults.tile_grid),
+                screen: overlays.screen_color.unwrap_or(defaults.screen),
+                camera: overlays.camera_color.unwrap_or(defaults.camera),
+            },
+            None => defaults,
+        }
+    }
+
+    pub fn history_limit(&self) -> Limit {
+        let defaults = Limit::default();
+        let setting = |value: Option<usize>, default: Option<usize>| match value {
+            Some(0) => None,
+            Some(value) => Some(value),
+            None => default,
+        };
+        match self.history {
+            Some(ref history) => Limit {
+                steps: setting(history.max_steps, defaults.steps),
+                cost: setting(history.max_bytes, defaults.cost),
+            },
+            None => defaults,
+        }
+    }
 }
 
 #[derive(Default, Debug, Serialize, Deserialize)]
@@ -12,4 +54,89 @@ pub struct RecentSettings {
     pub map_height: Option<u8>,
     pub tileset_path: Option<String>,
     pub blockset_path: Option<String>,
+    /// CGB attributes of the blockset tiles, one byte per tile
+    pub attributes_path: Option<String>,
+    /// The eight CGB background palettes, 64 bytes of RGB555
+    pub bg_palettes_path: Option<String>,
+    /// Passable tiles for pokered, or the collision of each block quadrant for pokecrystal
+    pub collision_path: Option<String>,
+    pub project_type: Option<ProjectType>,
+}
+
+/// How a project stores its blocks
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProjectType {
+    /// A blockset whose tiles all come from the tileset, as in pokered
+    Pokered,
+    /// metatiles.bin and attributes.bin, as in pokecrystal
+    Pokecrystal,
+}
+
+impl Default for ProjectType {
+    fn default() -> Self {
+        ProjectType::Pokered
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PaletteChoice {
+    Gray,
+    Dmg,
+    Pocket,
+    Custom,
+}
+
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct DisplaySettings {
+    pub palette: Option<PaletteChoice>,
+    /// Colors of the four shades, lightest first, for the custom palette
+    pub custom_palette: Option<[RgbTriple; 4]>,
+}
+
+impl DisplaySettings {
+    pub fn palette(&self) -> RgbPalette {
+        match self.palette.unwrap_or(PaletteChoice::Gray) {
+            PaletteChoice::Gray => BASE_PALETTE,
+            PaletteChoice::Dmg => DMG_PALETTE,
+            PaletteChoice::Pocket => POCKET_PALETTE,
+            PaletteChoice::Custom => self.custom_palette.map_or(BASE_PALETTE, RgbPalette::from_shades),
+        }
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct OverlaySettings {
+    pub block_grid_color: Option<RgbTriple>,
+    pub tile_grid_color: Option<RgbTriple>,
+    pub screen_color: Option<RgbTriple>,
+    pub camera_color: Option<RgbTriple>,
+}
+
+/// Colors of the guides drawn over the map
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct OverlayColors {
+    pub block_grid: RgbTriple,
+    pub tile_grid: RgbTriple,
+    pub screen: RgbTriple,
+    pub camera: RgbTriple,
+}
+
+impl Default for OverlayColors {
+    fn default() -> Self {
+        OverlayColors {
+            block_grid: (0, 0, 0),
+            tile_grid: (128, 128, 128),
+            screen: (255, 0, 0),
+            camera: (0, 96, 255),
+        }
+    }
+}
+
+/// How much undo history to keep, 0 meaning no limit
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct HistorySettings {
+    pub max_steps: Option<usize>,
+    pub max_bytes: Option<usize>,
 }
@@ -2,6 +2,14 @@
 pub const TILE_SIZE: usize = 8;
 pub const TILES_IN_ROW: usize = 4;
 pub const BLOCK_SIZE: usize = TILE_SIZE * TILES_IN_ROW;
+pub const TILES_IN_BLOCK: usize = TILES_IN_ROW * TILES_IN_ROW;
+pub const TILES_IN_SHEET_ROW: usize = 16;
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+/// Distance the player walks in one step, and the size of the player sprite
+pub const STEP_SIZE: usize = TILE_SIZE * 2;
+/// Where the player stands on screen, from its top left corner
+pub const PLAYER_ON_SCREEN: (usize, usize) = (64, 64);
 
 pub type RgbTriple = (u8, u8, u8);
 
@@ -16,9 +24,39 @@ pub fn rgb_triple_from(chunks: &[u8]) -> RgbTriple {
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
 pub struct RgbPalette(pub RgbTriple, pub RgbTriple, pub RgbTriple, pub RgbTriple);
 
+impl RgbPalette {
+    /// Palette from the colors of the four shades, lightest first
+    pub fn from_shades(colors: [RgbTriple; 4]) -> Self {
+        RgbPalette(colors[3], colors[2], colors[1], colors[0])
+    }
+
+    /// Color for a Game Boy shade, from 0 (lightest) to 3 (darkest)
+    pub fn shade(&self, color: u8) -> RgbTriple {
+        match color {
+            0 => self.3,
+            1 => self.2,
+            2 => self.1,
+            _ => self.0,
+        }
+    }
+
+    /// Game Boy shade of a color, if it belongs to the palette
+    pub fn shade_of(&self, color: RgbTriple) -> Option<u8> {
+        (0..4).find(|&shade| self.shade(shade) == color)
+    }
+}
+
 pub const BASE_PALETTE: RgbPalette =
     RgbPalette((0, 0, 0), (85, 85, 85), (170, 170, 170), (255, 255, 255));
 
+/// Greens of the original Game Boy screen
+pub const DMG_PALETTE: RgbPalette =
+    RgbPalette((15, 56, 15), (48, 98, 48), (139, 172, 15), (155, 188, 15));
+
+/// Olive grays of the Game Boy Pocket screen
+pub const POCKET_PALETTE: RgbPalette =
+    RgbPalette((31, 31, 31), (77, 83, 60), (139, 149, 109), (196, 207, 161));
+
 pub const HOVER_PALETTE: RgbPalette =
     RgbPalette((0, 20, 40), (13, 73, 80), (46, 138, 106), (253, 244, 152));
 
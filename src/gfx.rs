@@ -0,0 +1,379 @@
+use std::cmp::max;
+use std::error::Error;
+use std::fmt;
+
+use constants::*;
+
+pub const TILE_PIXELS: usize = TILE_SIZE * TILE_SIZE;
+pub const TILE_1BPP_BYTES: usize = TILE_SIZE;
+pub const TILE_2BPP_BYTES: usize = TILE_SIZE * 2;
+
+/// An 8x8 tile as Game Boy shades (0 is the lightest, 3 the darkest), row by row
+pub type Tile = [u8; TILE_PIXELS];
+
+/// Tiles of a tileset the games copy to each VRAM bank, from tile id 0 on.
+/// Ids past them hold the font and other graphics loaded apart.
+pub const TILES_PER_BANK: usize = 0x60;
+
+/// Tiles as laid out in a tile sheet image, `columns` tiles per row
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TileSheet {
+    pub tiles: Vec<Tile>,
+    pub columns: usize,
+}
+
+impl TileSheet {
+    /// Rows of tiles in the image, the last one padded with blank tiles
+    pub fn rows(&self) -> usize {
+        let columns = max(1, self.columns);
+        max(1, self.tiles.len().div_ceil(columns))
+    }
+
+    /// Index in the tileset of tile `id` of VRAM bank `bank`: the first
+    /// `TILES_PER_BANK` tiles fill bank 0, the next ones bank 1
+    pub fn index(bank: u8, id: u8) -> Option<usize> {
+        if bank > 1 || id as usize >= TILES_PER_BANK {
+            return None;
+        }
+        Some(bank as usize * TILES_PER_BANK + id as usize)
+    }
+
+    /// VRAM bank and tile id of the tile at `index` in the tileset
+    pub fn bank_and_id(index: usize) -> Option<(u8, u8)> {
+        if index >= 2 * TILES_PER_BANK {
+            return None;
+        }
+        Some(((index / TILES_PER_BANK) as u8, (index % TILES_PER_BANK) as u8))
+    }
+
+    /// Tile `id` of VRAM bank `bank`, if the tileset has it
+    pub fn tile(&self, bank: u8, id: u8) -> Option<&Tile> {
+        Self::index(bank, id).and_then(|index| self.tiles.get(index))
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum GfxError {
+    Truncated { len: usize, tile_bytes: usize },
+    UnknownColor { x: usize, y: usize, color: RgbTriple },
+    NotMonochrome {
+        tile: usize,
+        x: usize,
+        y: usize,
+        shade: u8,
+    },
+}
+
+impl fmt::Display for GfxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GfxError::Truncated { len, tile_bytes } => write!(
+                f,
+                "tile data is {} bytes long, which is not a multiple of {}",
+                len,
+                tile_bytes
+            ),
+            GfxError::UnknownColor { x, y, color } => write!(
+                f,
+                "pixel ({}, {}) has color {:?}, which is not in the palette",
+                x,
+                y,
+                color
+            ),
+            GfxError::NotMonochrome { tile, x, y, shade } => write!(
+                f,
+                "pixel ({}, {}) of tile {} has shade {}, but 1bpp only allows 0 and 3",
+                x,
+                y,
+                tile,
+                shade
+            ),
+        }
+    }
+}
+
+impl Error for GfxError {}
+
+/// Decode planar 2bpp tile data: each row is a low bit plane byte followed by
+/// a high bit plane byte, with the leftmost pixel in the most significant bit.
+pub fn decode_2bpp(bytes: &[u8]) -> Result<Vec<Tile>, GfxError> {
+    if !bytes.len().is_multiple_of(TILE_2BPP_BYTES) {
+        return Err(GfxError::Truncated {
+            len: bytes.len(),
+            tile_bytes: TILE_2BPP_BYTES,
+        });
+    }
+
+    Ok(bytes
+        .chunks(TILE_2BPP_BYTES)
+        .map(|chunk| {
+            let mut tile = [0; TILE_PIXELS];
+            for (y, row) in chunk.chunks(2).enumerate() {
+                for x in 0..TILE_SIZE {
+                    let bit = 7 - x;
+                    let low = (row[0] >> bit) & 1;
+                    let high = (row[1] >> bit) & 1;
+                    tile[x + y * TILE_SIZE] = low | (high << 1);
+                }
+            }
+            tile
+        })
+        .collect())
+}
+
+/// Decode 1bpp tile data: one byte per row, set bits are the darkest shade
+pub fn decode_1bpp(bytes: &[u8]) -> Result<Vec<Tile>, GfxError> {
+    if !bytes.len().is_multiple_of(TILE_1BPP_BYTES) {
+        return Err(GfxError::Truncated {
+            len: bytes.len(),
+            tile_bytes: TILE_1BPP_BYTES,
+        });
+    }
+
+    Ok(bytes
+        .chunks(TILE_1BPP_BYTES)
+        .map(|chunk| {
+            let mut tile = [0; TILE_PIXELS];
+            for (y, row) in chunk.iter().enumerate() {
+                for x in 0..TILE_SIZE {
+                    tile[x + y * TILE_SIZE] = ((row >> (7 - x)) & 1) * 3;
+                }
+            }
+            tile
+        })
+        .collect())
+}
+
+pub fn encode_2bpp(tiles: &[Tile]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(tiles.len() * TILE_2BPP_BYTES);
+    for tile in tiles {
+        for row in tile.chunks(TILE_SIZE) {
+            let mut low = 0;
+            let mut high = 0;
+            for (x, &shade) in row.iter().enumerate() {
+                low |= (shade & 1) << (7 - x);
+                high |= ((shade >> 1) & 1) << (7 - x);
+            }
+            bytes.push(low);
+            bytes.push(high);
+        }
+    }
+    bytes
+}
+
+/// Encode tiles as 1bpp, which only works for tiles made of shades 0 and 3
+pub fn encode_1bpp(tiles: &[Tile]) -> Result<Vec<u8>, GfxError> {
+    let mut bytes = Vec::with_capacity(tiles.len() * TILE_1BPP_BYTES);
+    for (i, tile) in tiles.iter().enumerate() {
+        for (y, row) in tile.chunks(TILE_SIZE).enumerate() {
+            let mut byte = 0;
+            for (x, &shade) in row.iter().enumerate() {
+                match shade {
+                    0 => (),
+                    3 => byte |= 1 << (7 - x),
+                    _ => {
+                        return Err(GfxError::NotMonochrome {
+                            tile: i,
+                            x: x,
+                            y: y,
+                            shade: shade,
+                        })
+                    }
+                }
+            }
+            bytes.push(byte);
+        }
+    }
+    Ok(bytes)
+}
+
+/// Mirror a tile left to right
+pub fn flip_horizontal(tile: &Tile) -> Tile {
+    let mut flipped = [0; TILE_PIXELS];
+    for y in 0..TILE_SIZE {
+        for x in 0..TILE_SIZE {
+            flipped[x + y * TILE_SIZE] = tile[TILE_SIZE - 1 - x + y * TILE_SIZE];
+        }
+    }
+    flipped
+}
+
+/// Mirror a tile top to bottom
+pub fn flip_vertical(tile: &Tile) -> Tile {
+    let mut flipped = [0; TILE_PIXELS];
+    for y in 0..TILE_SIZE {
+        for x in 0..TILE_SIZE {
+            flipped[x + y * TILE_SIZE] = tile[x + (TILE_SIZE - 1 - y) * TILE_SIZE];
+        }
+    }
+    flipped
+}
+
+/// Rotate a tile a quarter turn clockwise
+pub fn rotate_clockwise(tile: &Tile) -> Tile {
+    let mut rotated = [0; TILE_PIXELS];
+    for y in 0..TILE_SIZE {
+        for x in 0..TILE_SIZE {
+            rotated[TILE_SIZE - 1 - y + x * TILE_SIZE] = tile[x + y * TILE_SIZE];
+        }
+    }
+    rotated
+}
+
+/// Move every pixel by `(dx, dy)`, wrapping around the edges
+pub fn shift(tile: &Tile, dx: i32, dy: i32) -> Tile {
+    let size = TILE_SIZE as i32;
+    let mut shifted = [0; TILE_PIXELS];
+    for y in 0..size {
+        for x in 0..size {
+            let (to_x, to_y) = ((x + dx % size + size) % size, (y + dy % size + size) % size);
+            shifted[(to_x + to_y * size) as usize] = tile[(x + y * size) as usize];
+        }
+    }
+    shifted
+}
+
+/// Flood fill the pixels connected to `(x, y)` that share its shade
+pub fn fill(tile: &mut Tile, x: usize, y: usize, shade: u8) {
+    let target = tile[x + y * TILE_SIZE];
+    if target == shade {
+        return;
+    }
+
+    let mut pending = vec![(x, y)];
+    while let Some((x, y)) = pending.pop() {
+        if tile[x + y * TILE_SIZE] != target {
+            continue;
+        }
+        tile[x + y * TILE_SIZE] = shade;
+
+        if x > 0 {
+            pending.push((x - 1, y));
+        }
+        if x + 1 < TILE_SIZE {
+            pending.push((x + 1, y));
+        }
+        if y > 0 {
+            pending.push((x, y - 1));
+        }
+        if y + 1 < TILE_SIZE {
+            pending.push((x, y + 1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use blockset::Blockset;
+
+    #[test]
+    fn decodes_bit_planes() {
+        let mut bytes = vec![0; TILE_2BPP_BYTES];
+        bytes[0] = 0b1010_0000;
+        bytes[1] = 0b1100_0000;
+        let tiles = decode_2bpp(&bytes).unwrap();
+
+        assert_eq!(tiles.len(), 1);
+        assert_eq!(&tiles[0][0..4], &[3, 2, 1, 0]);
+        assert!(tiles[0][4..].iter().all(|&px| px == 0));
+    }
+
+    #[test]
+    fn encodes_what_it_decodes() {
+        let bytes: Vec<u8> = (0..TILE_2BPP_BYTES as u8 * 2).map(|b| b.wrapping_mul(37)).collect();
+        assert_eq!(encode_2bpp(&decode_2bpp(&bytes).unwrap()), bytes);
+
+        let bytes: Vec<u8> = (0..TILE_1BPP_BYTES as u8).map(|b| b.wrapping_mul(53)).collect();
+        assert_eq!(encode_1bpp(&decode_1bpp(&bytes).unwrap()).unwrap(), bytes);
+    }
+
+    #[test]
+    fn keeps_tile_count_of_partial_rows() {
+        let bytes: Vec<u8> = (0..TILE_2BPP_BYTES * 17).map(|b| (b as u8).wrapping_mul(29)).collect();
+        let sheet = TileSheet {
+            tiles: decode_2bpp(&bytes).unwrap(),
+            columns: TILES_IN_SHEET_ROW,
+        };
+
+        assert_eq!(sheet.rows(), 2);
+        assert_eq!(sheet.tiles.len(), 17);
+        assert_eq!(encode_2bpp(&sheet.tiles), bytes);
+    }
+
+    #[test]
+    fn finds_tiles_in_both_vram_banks() {
+        let tiles: Vec<Tile> = (0..TILES_PER_BANK + 2).map(|i| [i as u8; TILE_PIXELS]).collect();
+        let sheet = TileSheet {
+            tiles: tiles,
+            columns: TILES_IN_SHEET_ROW,
+        };
+
+        /* a metatile using tile 0x01 of bank 1 */
+        let mut attributes = vec![0; TILES_IN_BLOCK];
+        attributes[5] = 0x08;
+        let blockset = Blockset::from_metatiles(&[0x01; TILES_IN_BLOCK], &attributes).unwrap();
+        let block = blockset.get(0).unwrap();
+        let bank = block.attributes(1, 1).bank;
+
+        assert_eq!(sheet.tile(bank, block.tile(1, 1)), Some(&[0x61; TILE_PIXELS]));
+        assert_eq!(sheet.tile(0, block.tile(0, 0)), Some(&[0x01; TILE_PIXELS]));
+        assert_eq!(sheet.tile(1, 0x02), None);
+        assert_eq!(sheet.tile(0, 0x60), None);
+        assert_eq!(TileSheet::bank_and_id(0x61), Some((1, 0x01)));
+        assert_eq!(TileSheet::bank_and_id(2 * TILES_PER_BANK), None);
+    }
+
+    #[test]
+    fn rejects_gray_in_1bpp() {
+        let mut tile = [3; TILE_PIXELS];
+        tile[10] = 1;
+        assert_eq!(
+            encode_1bpp(&[tile]),
+            Err(GfxError::NotMonochrome {
+                tile: 0,
+                x: 2,
+                y: 1,
+                shade: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn transforms_tiles() {
+        let mut tile = [0; TILE_PIXELS];
+        tile[1] = 3; /* (1, 0) */
+
+        assert_eq!(flip_horizontal(&tile)[6], 3);
+        assert_eq!(flip_vertical(&tile)[1 + 7 * TILE_SIZE], 3);
+        assert_eq!(rotate_clockwise(&tile)[7 + TILE_SIZE], 3);
+        assert_eq!(shift(&tile, -2, 1)[7 + TILE_SIZE], 3);
+
+        let turned = (0..4).fold(tile, |tile, _| rotate_clockwise(&tile));
+        assert_eq!(&turned[..], &tile[..]);
+    }
+
+    #[test]
+    fn fill_stops_at_other_shades() {
+        let mut tile = [0; TILE_PIXELS];
+        for y in 0..TILE_SIZE {
+            tile[4 + y * TILE_SIZE] = 2;
+        }
+        fill(&mut tile, 0, 0, 1);
+
+        assert_eq!(tile.iter().filter(|&&px| px == 1).count(), 4 * TILE_SIZE);
+        assert_eq!(tile[5], 0);
+    }
+
+    #[test]
+    fn rejects_partial_tiles() {
+        assert_eq!(
+            decode_2bpp(&[0; 20]),
+            Err(GfxError::Truncated {
+                len: 20,
+                tile_bytes: TILE_2BPP_BYTES,
+            })
+        );
+    }
+}
@@ -2,19 +2,33 @@ use std::io::prelude::*;
 use std::fs::File;
 use std::rc::Rc;
 use std::cell::RefCell;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use gtk;
 use gdk;
 use gtk::prelude::*;
-use gtk::{Builder, DrawingArea, Label, MenuItem, Window};
-use gdk::Gravity;
+use gtk::{Builder, Button, CheckButton, CheckMenuItem, ColorButton, DrawingArea, FileChooserAction, FileChooserButton, Grid,
+          Label, ListBox, MenuItem, RadioButton, RadioMenuItem, RadioToolButton, SpinButton, Window};
+use gdk::{Gravity, RGBA};
 use gdk_pixbuf::Pixbuf;
 
-use config::Config;
+use blockeditor::BlockEditor;
+use pixeleditor::{PixelEditor, PixelTool};
+use blockset::Blockset;
+use cgb::{rgb555_from, rgb_from_555, BgPalettes, TileAttributes, BG_PALETTES};
+use collision::Collision;
+use config::{Config, PaletteChoice, ProjectType, RecentSettings};
+use constants::{RgbTriple, BASE_PALETTE, TILE_SIZE, TILES_IN_SHEET_ROW};
+use gfx::{decode_1bpp, decode_2bpp, encode_1bpp, encode_2bpp, flip_horizontal, flip_vertical,
+          rotate_clockwise, shift, Tile, TileSheet};
+use history::Entry;
+use map::{Anchor, Map, Region};
+use palette::tiles_from_pixbuf;
+use position::{next_zoom, Positionable};
+use preview::WalkPreview;
 use tileset::Tileset;
-use maparea::Maparea;
-use position::get_event_pos;
+use maparea::{Maparea, Overlays};
+use tools::{Eyedropper, Fill, MoveSelection, Pencil, Select, Shape, ShapeTool, Tool};
 
 
 fn get_bytes_from_filepath(path: &str) -> Option<Vec<u8>> {
@@ -27,12 +41,64 @@ fn get_bytes_from_filepath(path: &str) -> Option<Vec<u8>> {
         .ok()
 }
 
+fn has_extension<P: AsRef<Path>>(path: P, extension: &str) -> bool {
+    path.as_ref()
+        .extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case(extension))
+}
+
+/// Load a tile sheet, either an image using `BASE_PALETTE` or raw 1bpp/2bpp tile data
+fn load_tile_sheet(path: &str) -> Result<TileSheet, String> {
+    let decode = if has_extension(path, "2bpp") {
+        decode_2bpp
+    } else if has_extension(path, "1bpp") {
+        decode_1bpp
+    } else {
+        let pix = Pixbuf::new_from_file(path).map_err(|err| format!("{}: {}", path, err))?;
+        let tiles = tiles_from_pixbuf(&pix, BASE_PALETTE).map_err(|err| format!("{}: {}", path, err))?;
+        return Ok(TileSheet {
+            tiles: tiles,
+            columns: pix.get_width() as usize / TILE_SIZE,
+        });
+    };
+
+    let bytes = get_bytes_from_filepath(path).ok_or_else(|| format!("Cannot read {}", path))?;
+    let tiles = decode(&bytes).map_err(|err| format!("{}: {}", path, err))?;
+    Ok(TileSheet {
+        tiles: tiles,
+        columns: TILES_IN_SHEET_ROW,
+    })
+}
+
+fn rgba_from_rgb((red, green, blue): RgbTriple) -> RGBA {
+    RGBA {
+        red: red as f64 / 255.,
+        green: green as f64 / 255.,
+        blue: blue as f64 / 255.,
+        alpha: 1.,
+    }
+}
+
+fn rgb_from_rgba(rgba: &RGBA) -> RgbTriple {
+    let channel = |value: f64| (value * 255.).round() as u8;
+    (channel(rgba.red), channel(rgba.green), channel(rgba.blue))
+}
+
+/// Everything `Gui::load_project` reads
+struct Project {
+    sheet: TileSheet,
+    blockset: Blockset,
+    map: Map,
+    bg_palettes: Option<BgPalettes>,
+    collision: Option<Collision>,
+}
 
 pub struct Gui {
     config: Rc<RefCell<Config>>,
     builder: Builder,
     window: Rc<RefCell<Window>>,
     maparea: Rc<RefCell<Option<Maparea>>>,
+    clipboard: Rc<RefCell<Option<Region>>>,
 }
 
 impl Gui {
@@ -46,6 +112,7 @@ impl Gui {
             builder: builder,
             window: Rc::new(RefCell::new(window)),
             maparea: Rc::new(RefCell::new(None)),
+            clipboard: Rc::new(RefCell::new(None)),
         }
     }
 
@@ -70,6 +137,24 @@ impl Gui {
         let ref maparea_cell = self.maparea;
         let config_cell = self.config.clone();
 
+        //
+        // NEW
+        //
+        let new: MenuItem = self.builder.get_object("menu_new").unwrap();
+
+        new.connect_activate(clone!(window_cell, maparea_cell, config_cell => move |_| {
+            Gui::new_map(&maparea_cell, &mut config_cell.borrow_mut(), &window_cell.borrow());
+        }));
+
+        //
+        // OPEN
+        //
+        let open: MenuItem = self.builder.get_object("menu_open").unwrap();
+
+        open.connect_activate(clone!(window_cell, maparea_cell, config_cell => move |_| {
+            Gui::open(&maparea_cell, &mut config_cell.borrow_mut(), &window_cell.borrow());
+        }));
+
         //
         // SAVE
         //
@@ -88,7 +173,7 @@ impl Gui {
             let _ = config.recent.as_ref()
                 .and_then(|recent| recent.map_path.clone())
                 .map(|map_path| {
-                    Gui::save_map(&maparea_cell.borrow(), map_path)
+                    Gui::save_map(&maparea_cell.borrow(), &mut config, map_path, &window_cell.borrow())
                 })
                 .ok_or_else(|| {
                     Gui::save_map_as(&maparea_cell.borrow(), &mut config, &window_cell.borrow());
@@ -96,6 +181,33 @@ impl Gui {
                 });
         }));
 
+        //
+        // EXPORT TILES
+        //
+        let export_tiles: MenuItem = self.builder.get_object("menu_export_tiles").unwrap();
+
+        export_tiles.connect_activate(clone!(window_cell, maparea_cell => move |_| {
+            Gui::export_tiles(&maparea_cell.borrow(), &window_cell.borrow());
+        }));
+
+        //
+        // RESIZE
+        //
+        let resize: MenuItem = self.builder.get_object("menu_resize").unwrap();
+
+        resize.connect_activate(clone!(window_cell, maparea_cell => move |_| {
+            Gui::resize_map(&maparea_cell, &window_cell.borrow());
+        }));
+
+        //
+        // BACKGROUND BLOCK
+        //
+        let background: MenuItem = self.builder.get_object("menu_background").unwrap();
+
+        background.connect_activate(clone!(window_cell, maparea_cell => move |_| {
+            Gui::choose_background(&maparea_cell, &window_cell.borrow());
+        }));
+
         //
         // UNDO & REDO
         //
@@ -103,7 +215,6 @@ impl Gui {
         undo.add_events(drawing_area_mask_bits!());
 
         undo.connect_activate(clone!(maparea_cell => move |_| {
-            /* TODO pop last action from history */
             maparea_cell.borrow_mut().as_mut().map(|maparea| maparea.undo());
         }));
 
@@ -111,10 +222,151 @@ impl Gui {
         redo.add_events(drawing_area_mask_bits!());
 
         redo.connect_activate(clone!(maparea_cell => move |_| {
-            /* TODO push next action from history */
             maparea_cell.borrow_mut().as_mut().map(|maparea| maparea.redo());
         }));
 
+        //
+        // CLIPBOARD
+        //
+        let ref clipboard_cell = self.clipboard;
+
+        let copy: MenuItem = self.builder.get_object("menu_copy").unwrap();
+
+        copy.connect_activate(clone!(maparea_cell, clipboard_cell => move |_| {
+            let region = maparea_cell.borrow().as_ref().and_then(|maparea| maparea.copy());
+            if region.is_some() {
+                *clipboard_cell.borrow_mut() = region;
+            }
+        }));
+
+        let cut: MenuItem = self.builder.get_object("menu_cut").unwrap();
+
+        cut.connect_activate(clone!(maparea_cell, clipboard_cell => move |_| {
+            let region = maparea_cell.borrow_mut().as_mut().and_then(|maparea| maparea.cut());
+            if region.is_some() {
+                *clipboard_cell.borrow_mut() = region;
+            }
+        }));
+
+        let paste: MenuItem = self.builder.get_object("menu_paste").unwrap();
+
+        paste.connect_activate(clone!(maparea_cell, clipboard_cell => move |_| {
+            clipboard_cell.borrow().clone().map(|region| {
+                maparea_cell.borrow_mut().as_mut().map(|maparea| maparea.start_paste(region))
+            });
+        }));
+
+        //
+        // SELECTION
+        //
+        let select_all: MenuItem = self.builder.get_object("menu_select_all").unwrap();
+
+        select_all.connect_activate(clone!(maparea_cell => move |_| {
+            maparea_cell.borrow_mut().as_mut().map(|maparea| maparea.select_all());
+        }));
+
+        let deselect: MenuItem = self.builder.get_object("menu_deselect").unwrap();
+
+        deselect.connect_activate(clone!(maparea_cell => move |_| {
+            maparea_cell.borrow_mut().as_mut().map(|maparea| maparea.select(None));
+        }));
+
+        let fill_selection: MenuItem = self.builder.get_object("menu_fill_selection").unwrap();
+
+        fill_selection.connect_activate(clone!(maparea_cell => move |_| {
+            maparea_cell.borrow_mut().as_mut().map(|maparea| {
+                let selected = maparea.tileset().borrow().selected;
+                selected.map(|block| maparea.fill_selection(block));
+            });
+        }));
+
+        let delete: MenuItem = self.builder.get_object("menu_delete").unwrap();
+
+        delete.connect_activate(clone!(maparea_cell => move |_| {
+            maparea_cell.borrow_mut().as_mut().map(|maparea| maparea.delete_selection());
+        }));
+
+        //
+        // ZOOM
+        //
+        let zooms: [(&str, fn(f64) -> f64); 3] = [
+            ("zoom_in", |scale| next_zoom(scale, true)),
+            ("zoom_out", |scale| next_zoom(scale, false)),
+            ("zoom_normal", |_| 1.),
+        ];
+        for &(name, zoom) in zooms.iter() {
+            let item: MenuItem = self.builder.get_object(name).unwrap();
+
+            item.connect_activate(clone!(maparea_cell => move |_| {
+                maparea_cell.borrow_mut().as_mut().map(|maparea| {
+                    let scale = zoom(maparea.scale());
+                    maparea.set_scale(scale)
+                });
+            }));
+        }
+
+        //
+        // OVERLAYS
+        //
+        let overlays: [(&str, fn(&mut Overlays) -> &mut bool); 4] = [
+            ("overlay_block_grid", |overlays| &mut overlays.block_grid),
+            ("overlay_tile_grid", |overlays| &mut overlays.tile_grid),
+            ("overlay_screen", |overlays| &mut overlays.screen),
+            ("overlay_camera", |overlays| &mut overlays.camera),
+        ];
+        for &(name, field) in overlays.iter() {
+            let item: CheckMenuItem = self.builder.get_object(name).unwrap();
+
+            item.connect_toggled(clone!(maparea_cell => move |item| {
+                maparea_cell.borrow_mut().as_mut().map(|maparea| {
+                    let mut overlays = maparea.overlays();
+                    *field(&mut overlays) = item.get_active();
+                    maparea.set_overlays(overlays)
+                });
+            }));
+        }
+
+        //
+        // WALK PREVIEW
+        //
+        let walk_preview: MenuItem = self.builder.get_object("walk_preview").unwrap();
+        walk_preview.connect_activate(clone!(window_cell, maparea_cell => move |_| {
+            WalkPreview::open(&window_cell.borrow(), maparea_cell.clone());
+        }));
+
+        //
+        // TOOLS
+        //
+        /* the toolbar mirrors the Tools menu, which does the switching */
+        let tools: [(&str, fn() -> Box<dyn Tool>); 8] = [
+            ("pencil", || Box::new(Pencil::default())),
+            ("eyedropper", || Box::new(Eyedropper::default())),
+            ("fill", || Box::new(Fill)),
+            ("rectangle", || Box::new(ShapeTool::new(Shape::Rectangle))),
+            ("filled_rectangle", || Box::new(ShapeTool::new(Shape::FilledRectangle))),
+            ("line", || Box::new(ShapeTool::new(Shape::Line))),
+            ("select", || Box::new(Select::default())),
+            ("move", || Box::new(MoveSelection::default())),
+        ];
+        for &(name, new_tool) in tools.iter() {
+            let item: RadioMenuItem = self.builder.get_object(&format!("tool_{}", name)).unwrap();
+            let button: RadioToolButton = self.builder
+                .get_object(&format!("toolbar_{}", name))
+                .unwrap();
+
+            item.connect_toggled(clone!(maparea_cell, button => move |item| {
+                if item.get_active() {
+                    button.set_active(true);
+                    maparea_cell.borrow_mut().as_mut().map(|maparea| maparea.set_tool(new_tool()));
+                }
+            }));
+
+            button.connect_toggled(clone!(item => move |button| {
+                if button.get_active() {
+                    item.set_active(true);
+                }
+            }));
+        }
     }
 
     fn save_map_as(maparea: &Option<Maparea>, config: &mut Config, window: &Window) {
@@ -131,64 +383,942 @@ impl Gui {
 
         if response == gtk::ResponseType::Ok.into() {
             let filename = filename.expect("filename is missing");
+            Gui::save_map(maparea, config, filename, window);
+        }
+    }
 
-            /* keep filename for future use */
-            Gui::save_map(maparea, filename.clone());
+    /// Write the map, keeping `filename` for future saves unless writing fails
+    fn save_map(maparea: &Option<Maparea>, config: &mut Config, filename: PathBuf, window: &Window) {
+        let maparea = match *maparea {
+            Some(ref maparea) => maparea,
+            None => return,
+        };
+
+        match maparea.map().save(&filename) {
+            Ok(()) => {
+                /* the map size is needed to load it again */
+                config.recent.as_mut().map(|recent| {
+                    recent.map_path = Some(filename);
+                    recent.map_width = Some(maparea.map().width());
+                    recent.map_height = Some(maparea.map().height());
+                });
+            }
+            Err(err) => {
+                Gui::show_error(window, &format!("Cannot save the map to {:?}: {}", filename, err));
+            }
+        }
+    }
+
+    fn show_error(window: &Window, message: &str) {
+        let dialog = gtk::MessageDialog::new(
+            Some(window),
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Error,
+            gtk::ButtonsType::Ok,
+            message,
+        );
+        dialog.run();
+        dialog.destroy();
+    }
+
+    /// Write the tile sheet as 2bpp, or as 1bpp if the chosen file ends in `.1bpp`
+    fn export_tiles(maparea: &Option<Maparea>, window: &Window) {
+        let tileset = match *maparea {
+            Some(ref maparea) => maparea.tileset(),
+            None => return,
+        };
+
+        let file_dialog = gtk::FileChooserDialog::new(
+            Some("Export Tiles"),
+            Some(window),
+            gtk::FileChooserAction::Save,
+        );
+        file_dialog.add_button("OK", gtk::ResponseType::Ok.into());
+        file_dialog.add_button("Cancel", gtk::ResponseType::Cancel.into());
+        let response = file_dialog.run();
+        let filename = file_dialog.get_filename();
+        file_dialog.destroy();
+
+        if response != gtk::ResponseType::Ok.into() {
+            return;
+        }
+        let filename = filename.expect("filename is missing");
+
+        let tiles = tileset.borrow().tiles().to_vec();
+        let encoded = if has_extension(&filename, "1bpp") {
+            encode_1bpp(&tiles)
+        } else {
+            Ok(encode_2bpp(&tiles))
+        };
+        let result = encoded
+            .map_err(|err| err.to_string())
+            .and_then(|bytes| {
+                File::create(&filename)
+                    .and_then(|mut f| f.write_all(&bytes))
+                    .map_err(|err| err.to_string())
+            });
+
+        if let Err(err) = result {
+            Gui::show_error(window, &format!("Cannot export tiles to {:?}: {}", filename, err));
+        }
+    }
+
+    /// Read the tile sheet, blockset, map, CGB and collision data described by `recent`
+    fn load_project(recent: &RecentSettings) -> Result<Project, String> {
+        let map_path = recent.map_path.as_ref().ok_or("No map_path provided")?;
+        let map_width = recent.map_width.ok_or("No map_width provided")?;
+        let map_height = recent.map_height.ok_or("No map_height provided")?;
+        let tileset_path = recent.tileset_path.as_ref().ok_or("No tileset_path provided")?;
+        let blockset_path = recent.blockset_path.as_ref().ok_or("No blockset_path provided")?;
+
+        let sheet = load_tile_sheet(tileset_path)?;
+        let blockset_bytes = get_bytes_from_filepath(blockset_path)
+            .ok_or_else(|| format!("Cannot read {}", blockset_path))?;
+        let attributes = match recent.attributes_path {
+            Some(ref path) => {
+                let bytes = get_bytes_from_filepath(path).ok_or_else(|| format!("Cannot read {}", path))?;
+                Some((path, bytes))
+            }
+            None => None,
+        };
+
+        let project_type = recent.project_type.unwrap_or_default();
+        let blockset = match project_type {
+            ProjectType::Pokered => {
+                let mut blockset = Blockset::from_bytes(&blockset_bytes, sheet.tiles.len())
+                    .map_err(|err| format!("Invalid blockset {}: {}", blockset_path, err))?;
+                if let Some((path, bytes)) = attributes {
+                    blockset
+                        .load_attributes(&bytes)
+                        .map_err(|err| format!("Invalid attributes {}: {}", path, err))?;
+                }
+                blockset
+            }
+            ProjectType::Pokecrystal => {
+                let (path, bytes) = attributes.ok_or("No attributes_path provided")?;
+                Blockset::from_metatiles(&blockset_bytes, &bytes)
+                    .map_err(|err| format!("Invalid metatiles {} or {}: {}", blockset_path, path, err))?
+            }
+        };
+        let map = Map::load(map_path, map_width, map_height)
+            .map_err(|err| format!("Invalid map {:?}: {}", map_path, err))?;
+        map.check_blocks(blockset.len())
+            .map_err(|err| format!("Invalid map {:?}: {}", map_path, err))?;
+
+        let bg_palettes = match recent.bg_palettes_path {
+            Some(ref path) => {
+                let bytes = get_bytes_from_filepath(path).ok_or_else(|| format!("Cannot read {}", path))?;
+                let palettes = BgPalettes::from_bytes(&bytes)
+                    .map_err(|err| format!("Invalid palettes {}: {}", path, err))?;
+                Some(palettes)
+            }
+            None => None,
+        };
+
+        let collision = match recent.collision_path {
+            Some(ref path) => {
+                let bytes = get_bytes_from_filepath(path).ok_or_else(|| format!("Cannot read {}", path))?;
+                let collision = match project_type {
+                    ProjectType::Pokered => Collision::from_passable_tiles(&bytes),
+                    ProjectType::Pokecrystal => Collision::from_quadrants(&bytes, blockset.len())
+                        .map_err(|err| format!("Invalid collision {}: {}", path, err))?,
+                };
+                Some(collision)
+            }
+            None => None,
+        };
+
+        Ok(Project {
+            sheet: sheet,
+            blockset: blockset,
+            map: map,
+            bg_palettes: bg_palettes,
+            collision: collision,
+        })
+    }
+
+    /// Ask for the project type, a map, its size, a tileset, a blockset and optional CGB and collision data
+    fn open_dialog(config: &Config, window: &Window) -> Option<RecentSettings> {
+        let dialog = gtk::Dialog::new_with_buttons(
+            Some("Open Map"),
+            Some(window),
+            gtk::DialogFlags::MODAL,
+            &[
+                ("Cancel", gtk::ResponseType::Cancel.into()),
+                ("Open", gtk::ResponseType::Ok.into()),
+            ],
+        );
+        dialog.set_default_response(gtk::ResponseType::Ok.into());
+
+        let recent = config.recent.as_ref();
+        let project_types = [
+            ("pokered", ProjectType::Pokered, "pokered: blockset"),
+            ("pokecrystal", ProjectType::Pokecrystal, "pokecrystal: metatiles and attributes"),
+        ];
+        let type_combo = gtk::ComboBoxText::new();
+        for &(id, project_type, text) in project_types.iter() {
+            type_combo.append(Some(id), text);
+            if recent.and_then(|recent| recent.project_type).unwrap_or_default() == project_type {
+                type_combo.set_active_id(Some(id));
+            }
+        }
+        let map_button = FileChooserButton::new("Map", FileChooserAction::Open);
+        let tileset_button = FileChooserButton::new("Tileset", FileChooserAction::Open);
+        let blockset_button = FileChooserButton::new("Blockset", FileChooserAction::Open);
+        let attributes_button = FileChooserButton::new("Attributes", FileChooserAction::Open);
+        let bg_palettes_button = FileChooserButton::new("Palettes", FileChooserAction::Open);
+        let collision_button = FileChooserButton::new("Collision", FileChooserAction::Open);
+        let width_spin = SpinButton::new_with_range(1., 255., 1.);
+        let height_spin = SpinButton::new_with_range(1., 255., 1.);
+
+        recent.and_then(|recent| recent.map_path.as_ref()).map(|path| {
+            map_button.set_filename(path)
+        });
+        recent.and_then(|recent| recent.tileset_path.as_ref()).map(|path| {
+            tileset_button.set_filename(path)
+        });
+        recent.and_then(|recent| recent.blockset_path.as_ref()).map(|path| {
+            blockset_button.set_filename(path)
+        });
+        recent.and_then(|recent| recent.attributes_path.as_ref()).map(|path| {
+            attributes_button.set_filename(path)
+        });
+        recent.and_then(|recent| recent.bg_palettes_path.as_ref()).map(|path| {
+            bg_palettes_button.set_filename(path)
+        });
+        recent.and_then(|recent| recent.collision_path.as_ref()).map(|path| {
+            collision_button.set_filename(path)
+        });
+        width_spin.set_value(recent.and_then(|recent| recent.map_width).unwrap_or(1) as f64);
+        height_spin.set_value(recent.and_then(|recent| recent.map_height).unwrap_or(1) as f64);
+
+        let grid = Grid::new();
+        grid.set_row_spacing(6);
+        grid.set_column_spacing(12);
+        grid.set_border_width(6);
+        let rows: [(&str, gtk::Widget); 9] = [
+            ("Project", type_combo.clone().upcast()),
+            ("Map", map_button.clone().upcast()),
+            ("Width", width_spin.clone().upcast()),
+            ("Height", height_spin.clone().upcast()),
+            ("Tileset", tileset_button.clone().upcast()),
+            ("Blockset or metatiles", blockset_button.clone().upcast()),
+            ("CGB attributes", attributes_button.clone().upcast()),
+            ("CGB palettes", bg_palettes_button.clone().upcast()),
+            ("Collision", collision_button.clone().upcast()),
+        ];
+        for (row, &(text, ref widget)) in rows.iter().enumerate() {
+            let label = Label::new(Some(text));
+            label.set_halign(gtk::Align::End);
+            grid.attach(&label, 0, row as i32, 1, 1);
+            grid.attach(widget, 1, row as i32, 1, 1);
+        }
+        dialog.get_content_area().add(&grid);
+        dialog.show_all();
+
+        let response = dialog.run();
+        let settings = RecentSettings {
+            map_path: map_button.get_filename(),
+            map_width: Some(width_spin.get_value_as_int() as u8),
+            map_height: Some(height_spin.get_value_as_int() as u8),
+            tileset_path: tileset_button
+                .get_filename()
+                .map(|path| path.to_string_lossy().into_owned()),
+            blockset_path: blockset_button
+                .get_filename()
+                .map(|path| path.to_string_lossy().into_owned()),
+            attributes_path: attributes_button
+                .get_filename()
+                .map(|path| path.to_string_lossy().into_owned()),
+            bg_palettes_path: bg_palettes_button
+                .get_filename()
+                .map(|path| path.to_string_lossy().into_owned()),
+            collision_path: collision_button
+                .get_filename()
+                .map(|path| path.to_string_lossy().into_owned()),
+            project_type: type_combo.get_active_id().and_then(|active| {
+                project_types
+                    .iter()
+                    .find(|&&(id, _, _)| id == active)
+                    .map(|&(_, project_type, _)| project_type)
+            }),
+        };
+        dialog.destroy();
+
+        if response == gtk::ResponseType::Ok.into() {
+            Some(settings)
+        } else {
+            None
+        }
+    }
+
+    fn open(maparea_cell: &Rc<RefCell<Option<Maparea>>>, config: &mut Config, window: &Window) {
+        let settings = match Gui::open_dialog(config, window) {
+            Some(settings) => settings,
+            None => return,
+        };
 
+        match Gui::load_project(&settings) {
+            Ok(project) => {
+                maparea_cell.borrow_mut().as_mut().map(|maparea| {
+                    {
+                        let tileset = maparea.tileset();
+                        let mut tileset = tileset.borrow_mut();
+                        tileset.load(project.sheet, project.blockset, project.bg_palettes);
+                        tileset.set_collision(project.collision);
+                    }
+                    maparea.set_map(project.map);
+                });
+                config.recent = Some(settings);
+            }
+            Err(err) => Gui::show_error(window, &err),
+        }
+    }
+
+    /// Ask for the size of a new map and the block to fill it with
+    fn new_map_dialog(window: &Window, max_block_id: u8, fill: u8) -> Option<(u8, u8, u8)> {
+        let dialog = gtk::Dialog::new_with_buttons(
+            Some("New Map"),
+            Some(window),
+            gtk::DialogFlags::MODAL,
+            &[
+                ("Cancel", gtk::ResponseType::Cancel.into()),
+                ("Create", gtk::ResponseType::Ok.into()),
+            ],
+        );
+        dialog.set_default_response(gtk::ResponseType::Ok.into());
+
+        let width_spin = SpinButton::new_with_range(1., 255., 1.);
+        let height_spin = SpinButton::new_with_range(1., 255., 1.);
+        let fill_spin = SpinButton::new_with_range(0., max_block_id as f64, 1.);
+        width_spin.set_value(10.);
+        height_spin.set_value(9.);
+        fill_spin.set_value(fill as f64);
+
+        let grid = Grid::new();
+        grid.set_row_spacing(6);
+        grid.set_column_spacing(12);
+        grid.set_border_width(6);
+        let rows: [(&str, &SpinButton); 3] = [
+            ("Width", &width_spin),
+            ("Height", &height_spin),
+            ("Fill block", &fill_spin),
+        ];
+        for (row, &(text, spin)) in rows.iter().enumerate() {
+            let label = Label::new(Some(text));
+            label.set_halign(gtk::Align::End);
+            grid.attach(&label, 0, row as i32, 1, 1);
+            grid.attach(spin, 1, row as i32, 1, 1);
+        }
+        dialog.get_content_area().add(&grid);
+        dialog.show_all();
+
+        let response = dialog.run();
+        let result = (
+            width_spin.get_value_as_int() as u8,
+            height_spin.get_value_as_int() as u8,
+            fill_spin.get_value_as_int() as u8,
+        );
+        dialog.destroy();
+
+        if response == gtk::ResponseType::Ok.into() {
+            Some(result)
+        } else {
+            None
+        }
+    }
+
+    fn new_map(maparea_cell: &Rc<RefCell<Option<Maparea>>>, config: &mut Config, window: &Window) {
+        let tileset = match *maparea_cell.borrow() {
+            Some(ref maparea) => maparea.tileset(),
+            None => return,
+        };
+        let (max_block_id, selected) = {
+            let tileset = tileset.borrow();
+            (tileset.get_max_block_id(), tileset.selected.unwrap_or(0))
+        };
+
+        if let Some((width, height, fill)) = Gui::new_map_dialog(window, max_block_id, selected) {
+            maparea_cell.borrow_mut().as_mut().map(|maparea| {
+                maparea.set_map(Map::new(width, height, fill))
+            });
+
+            /* the next Save asks for a filename */
             config.recent.as_mut().map(|recent| {
-                recent.map_path = Some(filename)
+                recent.map_path = None;
+                recent.map_width = Some(width);
+                recent.map_height = Some(height);
+            });
+        }
+    }
+
+    /// Ask for new map dimensions, an anchor and the block to fill new cells with
+    fn resize_dialog(
+        window: &Window,
+        size: (u8, u8),
+        max_block_id: u8,
+        fill: u8,
+    ) -> Option<(u8, u8, Anchor, u8)> {
+        let dialog = gtk::Dialog::new_with_buttons(
+            Some("Resize Map"),
+            Some(window),
+            gtk::DialogFlags::MODAL,
+            &[
+                ("Cancel", gtk::ResponseType::Cancel.into()),
+                ("Resize", gtk::ResponseType::Ok.into()),
+            ],
+        );
+        dialog.set_default_response(gtk::ResponseType::Ok.into());
+
+        let width_spin = SpinButton::new_with_range(1., 255., 1.);
+        let height_spin = SpinButton::new_with_range(1., 255., 1.);
+        let fill_spin = SpinButton::new_with_range(0., max_block_id as f64, 1.);
+        width_spin.set_value(size.0 as f64);
+        height_spin.set_value(size.1 as f64);
+        fill_spin.set_value(fill as f64);
+
+        let grid = Grid::new();
+        grid.set_row_spacing(6);
+        grid.set_column_spacing(12);
+        grid.set_border_width(6);
+        let rows: [(&str, &SpinButton); 3] = [
+            ("Width", &width_spin),
+            ("Height", &height_spin),
+            ("Fill block", &fill_spin),
+        ];
+        for (row, &(text, spin)) in rows.iter().enumerate() {
+            let label = Label::new(Some(text));
+            label.set_halign(gtk::Align::End);
+            grid.attach(&label, 0, row as i32, 1, 1);
+            grid.attach(spin, 1, row as i32, 1, 1);
+        }
+
+        /* 3x3 grid of anchor buttons, top left selected */
+        let anchor_label = Label::new(Some("Anchor"));
+        anchor_label.set_halign(gtk::Align::End);
+        anchor_label.set_valign(gtk::Align::Start);
+        grid.attach(&anchor_label, 0, 3, 1, 1);
+        let anchor_grid = Grid::new();
+        let mut anchor_buttons: Vec<(RadioButton, Anchor)> = Vec::new();
+        for (i, anchor) in Anchor::all().iter().enumerate() {
+            let button = match anchor_buttons.first() {
+                Some(&(ref first, _)) => RadioButton::new_from_widget(first),
+                None => RadioButton::new(),
+            };
+            anchor_grid.attach(&button, (i % 3) as i32, (i / 3) as i32, 1, 1);
+            anchor_buttons.push((button, *anchor));
+        }
+        grid.attach(&anchor_grid, 1, 3, 1, 1);
+
+        dialog.get_content_area().add(&grid);
+        dialog.show_all();
+
+        let response = dialog.run();
+        let anchor = anchor_buttons
+            .iter()
+            .find(|&&(ref button, _)| button.get_active())
+            .map_or(Anchor::TopLeft, |&(_, anchor)| anchor);
+        let result = (
+            width_spin.get_value_as_int() as u8,
+            height_spin.get_value_as_int() as u8,
+            anchor,
+            fill_spin.get_value_as_int() as u8,
+        );
+        dialog.destroy();
+
+        if response == gtk::ResponseType::Ok.into() {
+            Some(result)
+        } else {
+            None
+        }
+    }
+
+    fn resize_map(maparea_cell: &Rc<RefCell<Option<Maparea>>>, window: &Window) {
+        let (tileset, size) = match *maparea_cell.borrow() {
+            Some(ref maparea) => (
+                maparea.tileset(),
+                (maparea.map().width(), maparea.map().height()),
+            ),
+            None => return,
+        };
+        let (max_block_id, selected) = {
+            let tileset = tileset.borrow();
+            (tileset.get_max_block_id(), tileset.selected.unwrap_or(0))
+        };
+
+        if let Some((width, height, anchor, fill)) =
+            Gui::resize_dialog(window, size, max_block_id, selected)
+        {
+            maparea_cell.borrow_mut().as_mut().map(|maparea| {
+                maparea.resize(width, height, anchor, fill)
             });
         }
     }
 
-    fn save_map(maparea: &Option<Maparea>, filename: PathBuf) {
-        maparea.as_ref().map(|maparea| {
-            maparea.on_bytes(|bytes| {
-                let written_bytes = File::create(&filename).and_then(|mut f| f.write(bytes));
-                println!("{:?}", written_bytes);
+    /// Ask for the block left behind by cutting, deleting or moving a selection
+    fn background_dialog(window: &Window, max_block_id: u8, background: u8) -> Option<u8> {
+        let dialog = gtk::Dialog::new_with_buttons(
+            Some("Background Block"),
+            Some(window),
+            gtk::DialogFlags::MODAL,
+            &[
+                ("Cancel", gtk::ResponseType::Cancel.into()),
+                ("OK", gtk::ResponseType::Ok.into()),
+            ],
+        );
+        dialog.set_default_response(gtk::ResponseType::Ok.into());
+
+        let fill_spin = SpinButton::new_with_range(0., max_block_id as f64, 1.);
+        fill_spin.set_value(background as f64);
+
+        let grid = Grid::new();
+        grid.set_row_spacing(6);
+        grid.set_column_spacing(12);
+        grid.set_border_width(6);
+        let label = Label::new(Some("Background block"));
+        label.set_halign(gtk::Align::End);
+        grid.attach(&label, 0, 0, 1, 1);
+        grid.attach(&fill_spin, 1, 0, 1, 1);
+        dialog.get_content_area().add(&grid);
+        dialog.show_all();
+
+        let response = dialog.run();
+        let result = fill_spin.get_value_as_int() as u8;
+        dialog.destroy();
+
+        if response == gtk::ResponseType::Ok.into() {
+            Some(result)
+        } else {
+            None
+        }
+    }
+
+    fn choose_background(maparea_cell: &Rc<RefCell<Option<Maparea>>>, window: &Window) {
+        let (tileset, background) = match *maparea_cell.borrow() {
+            Some(ref maparea) => (maparea.tileset(), maparea.background()),
+            None => return,
+        };
+        let max_block_id = tileset.borrow().get_max_block_id();
+
+        if let Some(block) = Gui::background_dialog(window, max_block_id, background) {
+            maparea_cell.borrow_mut().as_mut().map(|maparea| maparea.set_background(block));
+        }
+    }
+
+    /* connected after the menu, so the editor refreshes once Open is done */
+    fn init_block_editor(&self) -> Rc<RefCell<BlockEditor>> {
+        let tileset_widget: DrawingArea = self.builder.get_object("tileset").unwrap();
+        let maparea_widget: DrawingArea = self.builder.get_object("maparea").unwrap();
+        let editor = BlockEditor::from_data(
+            self.builder.get_object("block_editor").expect("No block_editor found in builder"),
+            self.builder.get_object("tile_sheet").expect("No tile_sheet found in builder"),
+            self.builder.get_object("lbl_block").expect("No lbl_block found in builder"),
+            self.maparea.borrow().as_ref().unwrap().tileset(),
+            self.maparea.clone(),
+        );
+        let ref window_cell = self.window;
+        let config_cell = self.config.clone();
+
+        /* follow the selected block, wherever it gets selected from */
+        tileset_widget.connect_button_release_event(clone!(editor => move |_, _| {
+            editor.borrow().refresh();
+            Inhibit::default()
+        }));
+        maparea_widget.connect_button_release_event(clone!(editor => move |_, _| {
+            editor.borrow().refresh();
+            Inhibit::default()
+        }));
+        /* undo and redo may change the blockset too */
+        for name in ["menu_open", "menu_undo", "menu_redo"].iter() {
+            let item: MenuItem = self.builder.get_object(name).unwrap();
+            item.connect_activate(clone!(editor => move |_| editor.borrow().refresh()));
+        }
+
+        let add: Button = self.builder.get_object("btn_add_block").unwrap();
+        add.connect_clicked(clone!(editor, window_cell => move |_| {
+            let result = editor.borrow_mut().add_block();
+            if let Err(err) = result {
+                Gui::show_error(&window_cell.borrow(), &err);
+            }
+        }));
+
+        let remove: Button = self.builder.get_object("btn_remove_block").unwrap();
+        remove.connect_clicked(clone!(editor, window_cell => move |_| {
+            let result = editor.borrow_mut().remove_last_block();
+            if let Err(err) = result {
+                Gui::show_error(&window_cell.borrow(), &err);
+            }
+        }));
+
+        let save: Button = self.builder.get_object("btn_save_blockset").unwrap();
+        save.connect_clicked(clone!(editor, window_cell => move |_| {
+            let (path, attributes_path) = {
+                let config = config_cell.borrow();
+                let recent = config.recent.as_ref();
+                (
+                    recent.and_then(|recent| recent.blockset_path.clone()),
+                    recent.and_then(|recent| recent.attributes_path.clone()),
+                )
+            };
+            let result = match path {
+                Some(path) => editor
+                    .borrow()
+                    .save(&path)
+                    .map_err(|err| format!("Cannot save {}: {}", path, err)),
+                None => Err("No blockset file to save to".to_string()),
+            };
+            let result = result.and_then(|()| match attributes_path {
+                Some(path) => editor
+                    .borrow()
+                    .save_attributes(&path)
+                    .map_err(|err| format!("Cannot save {}: {}", path, err)),
+                None => Ok(()),
             });
+            if let Err(err) = result {
+                Gui::show_error(&window_cell.borrow(), &err);
+            }
+        }));
+
+        /* the CGB attributes given to the tiles put in the block */
+        let palette: SpinButton = self.builder.get_object("attr_palette").unwrap();
+        let flags: Vec<CheckButton> = ["attr_x_flip", "attr_y_flip", "attr_bank", "attr_priority"]
+            .iter()
+            .map(|id| self.builder.get_object(id).unwrap())
+            .collect();
+        let update = Rc::new(clone!(editor, palette, flags => move || {
+            editor.borrow_mut().set_attributes(TileAttributes {
+                palette: palette.get_value_as_int() as u8,
+                bank: flags[2].get_active() as u8,
+                x_flip: flags[0].get_active(),
+                y_flip: flags[1].get_active(),
+                priority: flags[3].get_active(),
+            });
+        }));
+        palette.connect_value_changed(clone!(update => move |_| update()));
+        for flag in &flags {
+            flag.connect_toggled(clone!(update => move |_| update()));
+        }
+
+        /* show the bank of the tile picked in the sheet */
+        let sheet_widget: DrawingArea = self.builder.get_object("tile_sheet").unwrap();
+        sheet_widget.connect_button_press_event(clone!(editor, flags => move |_, _| {
+            let tile = editor.borrow().tile() as usize;
+            TileSheet::bank_and_id(tile).map(|(bank, _)| flags[2].set_active(bank == 1));
+            Inhibit::default()
+        }));
+
+        /* show the attributes picked with a right click */
+        let block_widget: DrawingArea = self.builder.get_object("block_editor").unwrap();
+        block_widget.connect_button_press_event(clone!(editor => move |_, ev| {
+            if ev.as_ref().button == 3 {
+                let attributes = editor.borrow().attributes();
+                palette.set_value(attributes.palette as f64);
+                flags[0].set_active(attributes.x_flip);
+                flags[1].set_active(attributes.y_flip);
+                flags[2].set_active(attributes.bank == 1);
+                flags[3].set_active(attributes.priority);
+            }
+            Inhibit::default()
+        }));
+
+        editor
+    }
+
+    /// Side panel listing the undo tree, abandoned branches indented under the
+    /// step they branch off; clicking a step undoes and redoes up to it
+    fn init_history_panel(&self, block_editor: &Rc<RefCell<BlockEditor>>) {
+        let ref maparea_cell = self.maparea;
+        let list: ListBox = self.builder.get_object("history_list").unwrap();
+        /* ids of the steps in the rows of `list` */
+        let ids: Rc<RefCell<Vec<usize>>> = Rc::new(RefCell::new(Vec::new()));
+
+        list.connect_row_activated(clone!(maparea_cell, block_editor, ids => move |_, row| {
+            let id = ids.borrow().get(row.get_index() as usize).cloned();
+            if let Some(id) = id {
+                maparea_cell.borrow_mut().as_mut().map(|maparea| maparea.jump_to(id));
+            }
+            block_editor.borrow().refresh();
+        }));
+
+        /* the map tells about every step, undo and redo, whatever started it */
+        maparea_cell.borrow_mut().as_mut().map(|maparea| {
+            maparea.connect_history_changed(clone!(list, ids => move |entries| {
+                Gui::refresh_history(&list, entries, &mut ids.borrow_mut());
+            }))
         });
     }
 
-    pub fn load_map(&self, filename: &PathBuf) -> Vec<u8> {
-        let mut mapset: Vec<u8> = Vec::new();
-        let mut mapset_file = File::open(filename).expect(&format!("Invalid path: {:?}", filename));
-        let result = mapset_file.read_to_end(&mut mapset);
-        if let Err(err) = result {
-            println!("{}", err);
+    /// List the steps of the map history in `list`, keeping their ids in `ids`
+    fn refresh_history(list: &ListBox, entries: &[Entry], ids: &mut Vec<usize>) {
+        for child in list.get_children() {
+            list.remove(&child);
+        }
+        let mut current = 0;
+        let mut past = true;
+        for (index, entry) in entries.iter().enumerate() {
+            let text = if entry.label.is_empty() { "Start" } else { &entry.label };
+            let label = Label::new(Some(text));
+            label.set_halign(gtk::Align::Start);
+            label.set_margin_start(12 * entry.indent as i32);
+            /* what redo would bring back, and the branches it wouldn't */
+            label.set_sensitive(entry.on_line && past);
+            list.insert(&label, -1);
+            if entry.current {
+                current = index;
+                past = false;
+            }
+        }
+        list.show_all();
+        list.select_row(list.get_row_at_index(current as i32).as_ref());
+        *ids = entries.iter().map(|entry| entry.id).collect();
+    }
+
+    fn init_pixel_editor(&self, block_editor: &Rc<RefCell<BlockEditor>>) {
+        let editor = PixelEditor::from_data(
+            self.builder.get_object("pixel_editor").expect("No pixel_editor found in builder"),
+            self.builder.get_object("shade_picker").expect("No shade_picker found in builder"),
+            self.builder.get_object("lbl_tile").expect("No lbl_tile found in builder"),
+            self.maparea.borrow().as_ref().unwrap().tileset(),
+            self.maparea.clone(),
+        );
+
+        /* edit the tile chosen in the block editor */
+        for id in &["tile_sheet", "block_editor"] {
+            let widget: DrawingArea = self.builder.get_object(id).unwrap();
+            widget.connect_button_press_event(clone!(editor, block_editor => move |_, _| {
+                let tile = block_editor.borrow().tile();
+                editor.borrow_mut().set_tile_id(tile);
+                Inhibit::default()
+            }));
+        }
+        let open: MenuItem = self.builder.get_object("menu_open").unwrap();
+        open.connect_activate(clone!(editor => move |_| editor.borrow_mut().reset()));
+
+        let tools = [("px_pencil", PixelTool::Pencil), ("px_fill", PixelTool::Fill)];
+        for &(id, tool) in tools.iter() {
+            let button: RadioButton = self.builder.get_object(id).unwrap();
+            button.connect_toggled(clone!(editor => move |button| {
+                if button.get_active() {
+                    editor.borrow_mut().set_tool(tool);
+                }
+            }));
+        }
+
+        let transforms: [(&str, fn(&Tile) -> Tile); 7] = [
+            ("px_flip_h", flip_horizontal),
+            ("px_flip_v", flip_vertical),
+            ("px_rotate", rotate_clockwise),
+            ("px_shift_left", |tile| shift(tile, -1, 0)),
+            ("px_shift_right", |tile| shift(tile, 1, 0)),
+            ("px_shift_up", |tile| shift(tile, 0, -1)),
+            ("px_shift_down", |tile| shift(tile, 0, 1)),
+        ];
+        for &(id, transform) in transforms.iter() {
+            let button: Button = self.builder.get_object(id).unwrap();
+            button.connect_clicked(clone!(editor => move |_| {
+                editor.borrow_mut().transform(transform);
+            }));
         }
 
-        mapset
+        let undo: Button = self.builder.get_object("px_undo").unwrap();
+        undo.connect_clicked(clone!(editor => move |_| editor.borrow_mut().undo()));
+    }
+
+    fn init_bg_palettes(&self) {
+        let ref window_cell = self.window;
+        let ref maparea_cell = self.maparea;
+        let config_cell = self.config.clone();
+        let tileset = self.maparea.borrow().as_ref().unwrap().tileset();
+        let grid: Grid = self.builder.get_object("bg_palettes_grid").unwrap();
+        let shown: CheckButton = self.builder.get_object("cgb_colors").unwrap();
+
+        /* kept while the CGB colors are hidden, so they can be shown again */
+        let palettes = Rc::new(RefCell::new(tileset.borrow().bg_palettes().unwrap_or_default()));
+
+        let mut buttons = Vec::new();
+        for index in 0..BG_PALETTES {
+            let label = Label::new(Some(index.to_string().as_str()));
+            grid.attach(&label, 0, index as i32, 1, 1);
+            for color in 0..4 {
+                let button = ColorButton::new();
+                button.connect_color_set(clone!(palettes, shown, maparea_cell => move |button| {
+                    let rgb = rgb_from_rgba(&button.get_rgba());
+                    palettes.borrow_mut().colors[index][color] = rgb555_from(rgb);
+                    if shown.get_active() {
+                        let palettes = *palettes.borrow();
+                        maparea_cell.borrow_mut().as_mut().map(|maparea| {
+                            maparea.set_bg_palettes(Some(palettes))
+                        });
+                    }
+                }));
+                grid.attach(&button, color as i32 + 1, index as i32, 1, 1);
+                buttons.push(button);
+            }
+        }
+        grid.show_all();
+
+        let sync = Rc::new(clone!(tileset, palettes, shown, buttons => move || {
+            let loaded = tileset.borrow().bg_palettes();
+            if let Some(loaded) = loaded {
+                *palettes.borrow_mut() = loaded;
+            }
+            let colors = palettes.borrow().colors;
+            for (button, &color) in buttons.iter().zip(colors.iter().flat_map(|palette| palette.iter())) {
+                button.set_rgba(&rgba_from_rgb(rgb_from_555(color)));
+            }
+            shown.set_active(loaded.is_some());
+        }));
+        /* synced before connecting, so startup doesn't redraw the map again */
+        sync();
+
+        shown.connect_toggled(clone!(palettes, maparea_cell => move |shown| {
+            let bg_palettes = if shown.get_active() {
+                Some(*palettes.borrow())
+            } else {
+                None
+            };
+            maparea_cell.borrow_mut().as_mut().map(|maparea| maparea.set_bg_palettes(bg_palettes));
+        }));
+
+        let open: MenuItem = self.builder.get_object("menu_open").unwrap();
+        open.connect_activate(clone!(sync => move |_| sync()));
+
+        let save: Button = self.builder.get_object("btn_save_palettes").unwrap();
+        save.connect_clicked(clone!(window_cell => move |_| {
+            let path = config_cell.borrow().recent.as_ref().and_then(|recent| {
+                recent.bg_palettes_path.clone()
+            });
+            let result = match path {
+                Some(path) => File::create(&path)
+                    .and_then(|mut f| f.write_all(&palettes.borrow().to_bytes()))
+                    .map_err(|err| format!("Cannot save {}: {}", path, err)),
+                None => Err("No palettes file to save to".to_string()),
+            };
+            if let Err(err) = result {
+                Gui::show_error(&window_cell.borrow(), &err);
+            }
+        }));
+    }
+
+    /// Ask for the colors of the four shades, lightest first
+    fn custom_palette_dialog(window: &Window, colors: [RgbTriple; 4]) -> Option<[RgbTriple; 4]> {
+        let dialog = gtk::Dialog::new_with_buttons(
+            Some("Custom Palette"),
+            Some(window),
+            gtk::DialogFlags::MODAL,
+            &[
+                ("Cancel", gtk::ResponseType::Cancel.into()),
+                ("Apply", gtk::ResponseType::Ok.into()),
+            ],
+        );
+        dialog.set_default_response(gtk::ResponseType::Ok.into());
+
+        let grid = Grid::new();
+        grid.set_row_spacing(6);
+        grid.set_column_spacing(12);
+        grid.set_border_width(6);
+        let names = ["Lightest", "Light", "Dark", "Darkest"];
+        let buttons: Vec<ColorButton> = colors
+            .iter()
+            .map(|&color| ColorButton::new_with_rgba(&rgba_from_rgb(color)))
+            .collect();
+        for (row, (name, button)) in names.iter().zip(&buttons).enumerate() {
+            let label = Label::new(Some(*name));
+            label.set_halign(gtk::Align::End);
+            grid.attach(&label, 0, row as i32, 1, 1);
+            grid.attach(button, 1, row as i32, 1, 1);
+        }
+        dialog.get_content_area().add(&grid);
+        dialog.show_all();
+
+        let response = dialog.run();
+        let mut result = colors;
+        for (color, button) in result.iter_mut().zip(&buttons) {
+            *color = rgb_from_rgba(&button.get_rgba());
+        }
+        dialog.destroy();
+
+        if response == gtk::ResponseType::Ok.into() {
+            Some(result)
+        } else {
+            None
+        }
+    }
+
+    fn init_palette_menu(&self) {
+        let ref window_cell = self.window;
+        let ref maparea_cell = self.maparea;
+        let config_cell = self.config.clone();
+        let side_panel: gtk::Notebook = self.builder.get_object("side_panel").unwrap();
+
+        let current = config_cell
+            .borrow()
+            .display
+            .as_ref()
+            .and_then(|display| display.palette)
+            .unwrap_or(PaletteChoice::Gray);
+
+        let choices = [
+            ("gray", PaletteChoice::Gray),
+            ("dmg", PaletteChoice::Dmg),
+            ("pocket", PaletteChoice::Pocket),
+            ("custom", PaletteChoice::Custom),
+        ];
+        for &(name, choice) in choices.iter() {
+            let item: RadioMenuItem = self.builder
+                .get_object(&format!("palette_{}", name))
+                .unwrap();
+            /* set before connecting, so the startup choice doesn't open the custom dialog */
+            if choice == current {
+                item.set_active(true);
+            }
+
+            item.connect_activate(clone!(window_cell, maparea_cell, config_cell, side_panel => move |item| {
+                if !item.get_active() {
+                    return;
+                }
+
+                let custom = if choice == PaletteChoice::Custom {
+                    let colors = {
+                        let config = config_cell.borrow();
+                        let palette = config.display_palette();
+                        config.display.as_ref().and_then(|display| display.custom_palette)
+                            .unwrap_or_else(|| [palette.shade(0), palette.shade(1), palette.shade(2), palette.shade(3)])
+                    };
+                    match Gui::custom_palette_dialog(&window_cell.borrow(), colors) {
+                        Some(colors) => Some(colors),
+                        None => return,
+                    }
+                } else {
+                    None
+                };
+
+                let palette = {
+                    let mut config = config_cell.borrow_mut();
+                    {
+                        let display = config.display.get_or_insert_with(Default::default);
+                        display.palette = Some(choice);
+                        if custom.is_some() {
+                            display.custom_palette = custom;
+                        }
+                    }
+                    config.display_palette()
+                };
+                maparea_cell.borrow_mut().as_mut().map(|maparea| maparea.set_palette(palette));
+                side_panel.queue_draw();
+            }));
+        }
     }
 
     pub fn run(&mut self) {
         {
             let config = self.config.borrow();
-            let map_height = config.recent.as_ref().unwrap().map_height.unwrap();
-            let map_width = config.recent.as_ref().unwrap().map_width.unwrap();
-            let mapset = config
+            let project = config
                 .recent
                 .as_ref()
-                .and_then({
-                    |recent| recent.map_path.as_ref()
-                })
-                .map(|map_path| self.load_map(map_path))
-                .expect("Error on loading mapset");
-
-            let tileset_path = config
-                .recent
-                .as_ref()
-                .unwrap()
-                .tileset_path
-                .as_ref()
-                .expect("No tileset_path provided");
-            let blockset_path = config
-                .recent
-                .as_ref()
-                .unwrap()
-                .blockset_path
-                .as_ref()
-                .expect("No blockset_path provided");
+                .ok_or_else(|| "No recent settings provided".to_string())
+                .and_then(Gui::load_project)
+                .unwrap_or_else(|err| panic!("{}", err));
 
             let lbl_coords: Label = self.builder.get_object("lblCoords").expect(
                 "No lblCoords found in builder",
@@ -200,28 +1330,41 @@ impl Gui {
                 "No maparea found in builder",
             );
 
-            // TODO need better error handling
-            let blockset: Vec<u8> = get_bytes_from_filepath(blockset_path).unwrap();
-            // TODO need better error handling
-            let tileset_pix = Pixbuf::new_from_file(tileset_path).unwrap();
-            let tileset = Tileset::from_data(tileset_widget, &blockset, &tileset_pix);
+            let tileset = Tileset::from_data(tileset_widget, project.blockset, project.sheet);
             tileset.borrow_mut().select_tile_at(0);
+            tileset.borrow_mut().set_palette(config.display_palette());
+            tileset.borrow_mut().set_bg_palettes(project.bg_palettes);
+            tileset.borrow_mut().set_collision(project.collision);
 
-            self.maparea =
-                Maparea::from_data(maparea_widget, map_width, map_height, mapset, tileset);
-            self.maparea.borrow().as_ref().map(|maparea| {
-                let lbl_coords = lbl_coords.clone();
-                maparea.widget.connect_motion_notify_event(move |_, ev| {
-                    let pos = get_event_pos(ev.get_position());
-                    lbl_coords.set_label(&format!("{:?}", pos));
-                    Inhibit::default()
-                });
-            });
+            self.maparea = Maparea::from_data(maparea_widget.clone(), project.map, tileset);
             Maparea::connect_events(&self.maparea);
+            self.maparea
+                .borrow_mut()
+                .as_mut()
+                .map(|maparea| {
+                    maparea.set_overlay_colors(config.overlay_colors());
+                    maparea.set_history_limit(config.history_limit());
+                });
+
+            /* connected last, so the maparea is up to date when the label is */
+            let ref maparea_cell = self.maparea;
+            maparea_widget.connect_motion_notify_event(clone!(maparea_cell, lbl_coords => move |_, _| {
+                maparea_cell.borrow().as_ref().map(|maparea| lbl_coords.set_label(&maparea.status()));
+                Inhibit::default()
+            }));
+            maparea_widget.connect_button_release_event(clone!(maparea_cell, lbl_coords => move |_, _| {
+                maparea_cell.borrow().as_ref().map(|maparea| lbl_coords.set_label(&maparea.status()));
+                Inhibit::default()
+            }));
         }
 
         // Menu
         self.init_menu();
+        let block_editor = self.init_block_editor();
+        self.init_pixel_editor(&block_editor);
+        self.init_history_panel(&block_editor);
+        self.init_palette_menu();
+        self.init_bg_palettes();
 
         // UI initialization.
         self.init_window();
@@ -1,38 +1,510 @@
+use std::collections::BTreeMap;
+
+/// Rough memory taken by an edit, in bytes, for `Limit::cost`
+pub trait Cost {
+    fn cost(&self) -> usize;
+}
+
+/// How much a `History` keeps before forgetting its oldest steps
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Limit {
+    /// Most undo steps kept
+    pub steps: Option<usize>,
+    /// Most bytes kept, the latest step being kept whatever its size
+    pub cost: Option<usize>,
+}
+
+impl Default for Limit {
+    fn default() -> Self {
+        Limit {
+            steps: Some(1000),
+            cost: Some(16 << 20),
+        }
+    }
+}
+
+/// The edits made by one action, and how to show the action to the user
+#[derive(Clone, Debug)]
+struct Step<C> {
+    label: String,
+    edits: Vec<C>,
+}
+
 #[derive(Clone, Debug)]
-pub struct History {
-    init: Vec<u8>,
-    prev: Vec<Vec<u8>>,
-    next: Vec<Vec<u8>>,
+struct Node<C> {
+    step: Step<C>,
+    parent: Option<usize>,
+    /// Oldest first
+    children: Vec<usize>,
+    /// Child that redo goes to: the last one made or gone through
+    active: Option<usize>,
 }
 
-impl History {
-    pub fn new(init: Vec<u8>) -> Self {
+/// A step as listed by `History::tree`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Entry {
+    pub id: usize,
+    /// Empty for the state the history starts from
+    pub label: String,
+    /// How many branches away from the line undo and redo follow
+    pub indent: usize,
+    /// Whether undo and redo reach it without switching branches
+    pub on_line: bool,
+    pub current: bool,
+}
+
+/// Undo tree of steps, each step being the edits `C` made by one action.
+///
+/// Undoing and making a new edit starts a branch, keeping the undone steps
+/// around. Undo goes back to the parent step, redo to the child last made or
+/// gone through, so without switching branches the tree acts as linear undo.
+///
+/// The history only keeps the edits: whoever owns it applies them, reverting a
+/// step's edits last to first.
+#[derive(Clone, Debug)]
+pub struct History<C> {
+    /// By id, ids growing with age
+    nodes: BTreeMap<usize, Node<C>>,
+    root: usize,
+    current: usize,
+    next_id: usize,
+    limit: Limit,
+    /// Bytes taken by every step, done or undone
+    cost: usize,
+    /// Nesting of `begin_group` calls
+    grouping: usize,
+    /// Whether the current step is the group still being built
+    group_open: bool,
+}
+
+impl<C: Clone + Cost> History<C> {
+    pub fn new(limit: Limit) -> Self {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(
+            0,
+            Node {
+                step: Step {
+                    label: String::new(),
+                    edits: Vec::new(),
+                },
+                parent: None,
+                children: Vec::new(),
+                active: None,
+            },
+        );
         History {
-            init: init,
-            prev: Vec::new(),
-            next: Vec::new(),
+            nodes: nodes,
+            root: 0,
+            current: 0,
+            next_id: 1,
+            limit: limit,
+            cost: 0,
+            grouping: 0,
+            group_open: false,
+        }
+    }
+
+    pub fn limit(&self) -> Limit {
+        self.limit
+    }
+
+    pub fn set_limit(&mut self, limit: Limit) {
+        self.limit = limit;
+        self.enforce_limit();
+    }
+
+    /// Record the edits of a new step, branching off if steps were undone
+    pub fn push(&mut self, label: &str, edits: Vec<C>) {
+        if edits.is_empty() {
+            return;
+        }
+        self.cost += Self::step_cost(&edits);
+
+        if self.group_open {
+            self.node_mut(self.current).step.edits.extend(edits);
+        } else {
+            let id = self.next_id;
+            self.next_id += 1;
+            self.nodes.insert(
+                id,
+                Node {
+                    step: Step {
+                        label: label.to_string(),
+                        edits: edits,
+                    },
+                    parent: Some(self.current),
+                    children: Vec::new(),
+                    active: None,
+                },
+            );
+            {
+                let parent = self.node_mut(self.current);
+                parent.children.push(id);
+                parent.active = Some(id);
+            }
+            self.current = id;
+            self.group_open = self.grouping > 0;
+        }
+        self.enforce_limit();
+    }
+
+    /// Make the steps pushed until the matching `end_group` a single step,
+    /// labelled like the first of them
+    pub fn begin_group(&mut self) {
+        self.grouping += 1;
+    }
+
+    pub fn end_group(&mut self) {
+        self.grouping = self.grouping.saturating_sub(1);
+        if self.grouping == 0 {
+            self.group_open = false;
+        }
+    }
+
+    /// The edits of the step to revert, last to first, if there is one
+    pub fn undo(&mut self) -> Option<Vec<C>> {
+        self.group_open = false;
+        let (parent, edits) = {
+            let node = &self.nodes[&self.current];
+            (node.parent?, node.step.edits.clone())
+        };
+        self.node_mut(parent).active = Some(self.current);
+        self.current = parent;
+        Some(edits)
+    }
+
+    /// The edits of the step to apply again, if there is one
+    pub fn redo(&mut self) -> Option<Vec<C>> {
+        self.group_open = false;
+        let child = self.nodes[&self.current].active?;
+        self.current = child;
+        Some(self.nodes[&child].step.edits.clone())
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.nodes[&self.current].parent.is_some()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.nodes[&self.current].active.is_some()
+    }
+
+    /// Id of the present state
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn contains(&self, id: usize) -> bool {
+        self.nodes.contains_key(&id)
+    }
+
+    /// Whether `ancestor` is `id` or lies on the way from `id` to the start
+    pub fn is_ancestor(&self, ancestor: usize, id: usize) -> bool {
+        let mut node = Some(id);
+        while let Some(id) = node {
+            if id == ancestor {
+                return true;
+            }
+            node = self.nodes.get(&id).and_then(|node| node.parent);
+        }
+        false
+    }
+
+    /// Switch to the branches leading to `id`, so that redo gets there
+    /// from any step before it
+    pub fn activate(&mut self, id: usize) {
+        let mut child = id;
+        while let Some(parent) = self.nodes.get(&child).and_then(|node| node.parent) {
+            self.node_mut(parent).active = Some(child);
+            child = parent;
+        }
+    }
+
+    /// Every step, the start included: each followed by the branches off it,
+    /// then by the step redo goes to
+    pub fn tree(&self) -> Vec<Entry> {
+        let line = self.line();
+        let mut entries = Vec::with_capacity(self.nodes.len());
+        let mut stack = vec![(self.root, 0)];
+        while let Some((id, indent)) = stack.pop() {
+            let node = &self.nodes[&id];
+            entries.push(Entry {
+                id: id,
+                label: node.step.label.clone(),
+                indent: indent,
+                on_line: line.contains(&id),
+                current: id == self.current,
+            });
+
+            /* popped last, so it comes after the branches */
+            if let Some(active) = node.active {
+                stack.push((active, indent));
+            }
+            for &child in node.children.iter().rev() {
+                if Some(child) != node.active {
+                    stack.push((child, indent + 1));
+                }
+            }
+        }
+        entries
+    }
+
+    /// Bytes taken by every step
+    pub fn cost(&self) -> usize {
+        self.cost
+    }
+
+    fn step_cost(step: &[C]) -> usize {
+        step.iter().map(Cost::cost).sum()
+    }
+
+    fn node_mut(&mut self, id: usize) -> &mut Node<C> {
+        self.nodes.get_mut(&id).expect("history node")
+    }
+
+    /// Ids of the steps undo and redo reach
+    fn line(&self) -> Vec<usize> {
+        let mut line = Vec::new();
+        let mut node = Some(self.current);
+        while let Some(id) = node {
+            line.push(id);
+            node = self.nodes[&id].parent;
+        }
+        let mut node = self.nodes[&self.current].active;
+        while let Some(id) = node {
+            line.push(id);
+            node = self.nodes[&id].active;
+        }
+        line
+    }
+
+    fn enforce_limit(&mut self) {
+        loop {
+            let steps = self.nodes.len() - 1;
+            let over = self.limit.steps.is_some_and(|limit| steps > limit) ||
+                self.limit.cost.is_some_and(|limit| steps > 1 && self.cost > limit);
+            if !over || !self.forget_oldest() {
+                break;
+            }
+        }
+    }
+
+    /// Forget the oldest of the steps that can go: abandoned branch tips
+    /// first, then the start when a single step follows it, then the last
+    /// step that can be redone
+    fn forget_oldest(&mut self) -> bool {
+        let current = self.current;
+        let root = self.root;
+        let line = self.line();
+        let leaves = || {
+            self.nodes
+                .iter()
+                .filter(|&(&id, node)| id != root && node.children.is_empty())
+                .map(|(&id, _)| id)
+        };
+        let root_can_go = root != current && self.nodes[&root].children.len() == 1;
+
+        let oldest = leaves()
+            .find(|id| !line.contains(id))
+            .or(if root_can_go { Some(root) } else { None })
+            .or_else(|| leaves().find(|&id| !self.is_ancestor(id, current)));
+
+        match oldest {
+            Some(id) if id == root => {
+                let child = self.nodes[&root].children[0];
+                self.nodes.remove(&root);
+                let node = self.node_mut(child);
+                node.parent = None;
+                /* it can't be undone any more, it's the start now */
+                let forgotten = Self::step_cost(&node.step.edits);
+                node.step.edits.clear();
+                node.step.label.clear();
+                self.cost -= forgotten;
+                self.root = child;
+                true
+            }
+            Some(id) => {
+                let node = self.nodes.remove(&id).expect("history node");
+                self.cost -= Self::step_cost(&node.step.edits);
+                if let Some(parent) = node.parent {
+                    let parent = self.node_mut(parent);
+                    parent.children.retain(|&child| child != id);
+                    if parent.active == Some(id) {
+                        parent.active = parent.children.last().cloned();
+                    }
+                }
+                true
+            }
+            None => false,
         }
     }
 }
 
-impl History {
-    pub fn redo(&mut self) -> Option<Vec<u8>> {
-        self.next.pop().map(|elem| {
-            self.prev.push(elem.clone());
-            elem
-        })
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    impl Cost for u32 {
+        fn cost(&self) -> usize {
+            *self as usize
+        }
+    }
+
+    #[test]
+    fn undoes_and_redoes_steps() {
+        let mut history = History::new(Limit::default());
+        history.push("step", vec![1, 2]);
+        history.push("step", vec![3]);
+
+        assert_eq!(history.undo(), Some(vec![3]));
+        assert_eq!(history.undo(), Some(vec![1, 2]));
+        assert_eq!(history.undo(), None);
+        assert_eq!(history.redo(), Some(vec![1, 2]));
+
+        history.push("step", vec![4]);
+        assert!(!history.can_redo());
+        assert_eq!(history.undo(), Some(vec![4]));
     }
 
-    pub fn undo(&mut self) -> Option<Vec<u8>> {
-        self.prev.pop().map(|elem| { self.next.push(elem); });
-        self.prev.pop().map_or(Some(self.init.clone()), |elem| {
-            self.prev.push(elem.clone());
-            Some(elem)
-        })
+    fn labels(history: &History<u32>) -> Vec<(String, usize)> {
+        history.tree().into_iter().map(|entry| (entry.label, entry.indent)).collect()
     }
 
-    pub fn update(&mut self, state: Vec<u8>) {
-        self.next.clear();
-        self.prev.push(state);
+    #[test]
+    fn keeps_abandoned_branches() {
+        let mut history = History::new(Limit::default());
+        history.push("Paint 2 blocks", vec![1]);
+        history.push("Fill", vec![2]);
+        history.push("Resize", vec![3]);
+        history.undo();
+        history.undo();
+        history.push("Line", vec![4]);
+
+        assert!(!history.can_redo());
+        assert_eq!(
+            labels(&history),
+            vec![
+                ("".to_string(), 0),
+                ("Paint 2 blocks".to_string(), 0),
+                ("Fill".to_string(), 1),
+                ("Resize".to_string(), 1),
+                ("Line".to_string(), 0),
+            ]
+        );
+        let entry = &history.tree()[4];
+        assert!(entry.on_line && entry.current);
+        assert!(!history.tree()[2].on_line);
+
+        /* back to the first branch, which then acts as linear undo again */
+        assert_eq!(history.undo(), Some(vec![4]));
+        history.activate(3);
+        assert_eq!(history.redo(), Some(vec![2]));
+        assert_eq!(history.redo(), Some(vec![3]));
+        assert_eq!(history.undo(), Some(vec![3]));
+        assert_eq!(history.redo(), Some(vec![3]));
+        assert!(history.is_ancestor(1, history.current()));
+        assert!(!history.is_ancestor(4, history.current()));
+    }
+
+    #[test]
+    fn groups_steps() {
+        let mut history = History::new(Limit::default());
+        history.begin_group();
+        history.push("Paste", vec![1]);
+        history.begin_group();
+        history.push("Fill", vec![2]);
+        history.end_group();
+        history.push("Fill", vec![3]);
+        history.end_group();
+        history.push("Resize", vec![4]);
+
+        assert_eq!(
+            labels(&history),
+            vec![
+                ("".to_string(), 0),
+                ("Paste".to_string(), 0),
+                ("Resize".to_string(), 0),
+            ]
+        );
+        assert_eq!(history.undo(), Some(vec![4]));
+        assert_eq!(history.undo(), Some(vec![1, 2, 3]));
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn forgets_the_oldest_steps() {
+        let mut history = History::new(Limit {
+            steps: Some(2),
+            cost: Some(10),
+        });
+        history.push("a", vec![1]);
+        history.push("b", vec![2]);
+        history.push("c", vec![3]);
+        assert_eq!(history.cost(), 5);
+        assert_eq!(
+            labels(&history),
+            vec![("".to_string(), 0), ("b".to_string(), 0), ("c".to_string(), 0)]
+        );
+
+        history.push("d", vec![8]);
+        assert_eq!(history.cost(), 8);
+        assert_eq!(labels(&history), vec![("".to_string(), 0), ("d".to_string(), 0)]);
+        history.push("e", vec![20]);
+        assert_eq!(history.cost(), 20);
+        assert_eq!(labels(&history), vec![("".to_string(), 0), ("e".to_string(), 0)]);
+        assert_eq!(history.undo(), Some(vec![20]));
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn counts_the_cost_of_undone_steps() {
+        let mut history = History::new(Limit::default());
+        history.push("step", vec![1, 2]);
+        history.push("step", vec![4]);
+        history.undo();
+        assert_eq!(history.cost(), 7);
+
+        /* the undone step stays around on its branch */
+        history.push("step", vec![8]);
+        assert_eq!(history.cost(), 15);
+    }
+
+    #[test]
+    fn keeps_the_undo_line_over_abandoned_branches() {
+        let mut history = History::new(Limit {
+            steps: Some(2),
+            cost: None,
+        });
+        history.push("a", vec![1]);
+        history.push("b", vec![2]);
+        history.undo();
+        history.push("c", vec![3]);
+
+        assert_eq!(
+            labels(&history),
+            vec![("".to_string(), 0), ("a".to_string(), 0), ("c".to_string(), 0)]
+        );
+        assert_eq!(history.undo(), Some(vec![3]));
+        assert_eq!(history.undo(), Some(vec![1]));
+        assert_eq!(history.undo(), None);
+    }
+
+    #[test]
+    fn forgets_abandoned_branches_first() {
+        let mut history = History::new(Limit {
+            steps: Some(2),
+            cost: None,
+        });
+        history.push("a", vec![1]);
+        history.undo();
+        history.push("b", vec![2]);
+        history.push("c", vec![3]);
+
+        assert_eq!(
+            labels(&history),
+            vec![("".to_string(), 0), ("b".to_string(), 0), ("c".to_string(), 0)]
+        );
+        assert_eq!(history.undo(), Some(vec![3]));
+        assert_eq!(history.undo(), Some(vec![2]));
     }
 }
@@ -1,3 +1,11 @@
+pub mod blockset;
+pub mod cgb;
+pub mod collision;
+pub mod constants;
+pub mod gfx;
+pub mod history;
+pub mod map;
+
 #[cfg(test)]
 mod test {
     #[test]
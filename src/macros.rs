@@ -20,6 +20,7 @@ macro_rules! drawing_area_mask_bits {
     () => (
         (gdk::EventMask::POINTER_MOTION_MASK
          | gdk::EventMask::BUTTON_PRESS_MASK
+         | gdk::EventMask::BUTTON_RELEASE_MASK
          | gdk::EventMask::BUTTON1_MOTION_MASK
          | gdk::EventMask::ENTER_NOTIFY_MASK
          | gdk::EventMask::LEAVE_NOTIFY_MASK).bits() as i32
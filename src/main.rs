@@ -1,15 +1,16 @@
 #[macro_use]
 mod macros;
-mod constants;
 
-mod rustmap;
+mod blockeditor;
 mod config;
 mod gui;
-mod history;
 mod maparea;
 mod position;
 mod palette;
+mod pixeleditor;
+mod preview;
 mod tileset;
+mod tools;
 
 #[macro_use]
 extern crate serde_derive;
@@ -18,12 +19,15 @@ extern crate cairo;
 extern crate gdk;
 extern crate gdk_pixbuf;
 extern crate gtk;
+extern crate rustmap;
 
 use std::io::prelude::*;
 use std::fs::File;
 
 use gtk::Builder;
 
+use rustmap::{blockset, cgb, collision, constants, gfx, history, map};
+
 use config::Config;
 use gui::Gui;
 
@@ -0,0 +1,1030 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use std::cmp::min;
+use std::collections::BTreeMap;
+use std::mem::size_of;
+
+use blockset::{Block, Blockset};
+use history::{Cost, Entry, History, Limit};
+
+/// A change made to a `Map`, to be picked up by whatever is displaying it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MapChange {
+    Block { index: usize, block: u8 },
+    Resized { width: u8, height: u8 },
+    /// The blockset the map is drawn with has to change, by `Blockset::apply_changes`
+    Blockset(Vec<(u8, Option<Block>)>),
+}
+
+/// Which part of a map stays in place when it is resized
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    pub fn all() -> [Anchor; 9] {
+        [
+            Anchor::TopLeft,
+            Anchor::Top,
+            Anchor::TopRight,
+            Anchor::Left,
+            Anchor::Center,
+            Anchor::Right,
+            Anchor::BottomLeft,
+            Anchor::Bottom,
+            Anchor::BottomRight,
+        ]
+    }
+
+    /// Horizontal and vertical position: 0 for start, 1 for center, 2 for end
+    fn weights(self) -> (i32, i32) {
+        match self {
+            Anchor::TopLeft => (0, 0),
+            Anchor::Top => (1, 0),
+            Anchor::TopRight => (2, 0),
+            Anchor::Left => (0, 1),
+            Anchor::Center => (1, 1),
+            Anchor::Right => (2, 1),
+            Anchor::BottomLeft => (0, 2),
+            Anchor::Bottom => (1, 2),
+            Anchor::BottomRight => (2, 2),
+        }
+    }
+}
+
+/// A rectangle of map cells
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Rect {
+    pub x: u8,
+    pub y: u8,
+    pub width: u8,
+    pub height: u8,
+}
+
+impl Rect {
+    /// Rectangle spanning two opposite corner cells, both included
+    pub fn from_corners(a: (u8, u8), b: (u8, u8)) -> Self {
+        let (x0, x1) = (a.0.min(b.0), a.0.max(b.0));
+        let (y0, y1) = (a.1.min(b.1), a.1.max(b.1));
+        Rect {
+            x: x0,
+            y: y0,
+            width: x1 - x0 + 1,
+            height: y1 - y0 + 1,
+        }
+    }
+
+    pub fn contains(&self, x: u8, y: u8) -> bool {
+        x >= self.x && (x - self.x) < self.width && y >= self.y && (y - self.y) < self.height
+    }
+
+    /// Cells of the rectangle, row by row
+    pub fn positions(&self) -> Vec<(u8, u8)> {
+        let mut positions = Vec::with_capacity(self.width as usize * self.height as usize);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                positions.push((self.x + x, self.y + y));
+            }
+        }
+        positions
+    }
+
+    /// Cells on the border of the rectangle
+    pub fn outline(&self) -> Vec<(u8, u8)> {
+        let (x1, y1) = (self.x + self.width - 1, self.y + self.height - 1);
+        self.positions()
+            .into_iter()
+            .filter(|&(x, y)| x == self.x || x == x1 || y == self.y || y == y1)
+            .collect()
+    }
+}
+
+/// Cells on a straight line between two cells, both included
+pub fn line(from: (u8, u8), to: (u8, u8)) -> Vec<(u8, u8)> {
+    let (mut x, mut y) = (from.0 as i32, from.1 as i32);
+    let (x1, y1) = (to.0 as i32, to.1 as i32);
+    let (dx, dy) = ((x1 - x).abs(), -(y1 - y).abs());
+    let (step_x, step_y) = ((x1 - x).signum(), (y1 - y).signum());
+    let mut err = dx + dy;
+
+    let mut cells = vec![(x as u8, y as u8)];
+    while (x, y) != (x1, y1) {
+        let err2 = 2 * err;
+        if err2 >= dy {
+            err += dy;
+            x += step_x;
+        }
+        if err2 <= dx {
+            err += dx;
+            y += step_y;
+        }
+        cells.push((x as u8, y as u8));
+    }
+    cells
+}
+
+/// A rectangle of blocks lifted from a map, e.g. for the clipboard
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Region {
+    pub width: u8,
+    pub height: u8,
+    blocks: Vec<u8>,
+}
+
+impl Region {
+    pub fn new(width: u8, height: u8, blocks: Vec<u8>) -> Self {
+        assert_eq!(blocks.len(), width as usize * height as usize);
+        Region {
+            width: width,
+            height: height,
+            blocks: blocks,
+        }
+    }
+
+    pub fn get(&self, x: u8, y: u8) -> u8 {
+        self.blocks[x as usize + y as usize * self.width as usize]
+    }
+}
+
+#[derive(Debug)]
+pub enum MapError {
+    Io(io::Error),
+    SizeMismatch { expected: usize, found: usize },
+    OutOfBounds { x: usize, y: usize },
+    UnknownBlock { x: usize, y: usize, block: u8, count: usize },
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MapError::Io(ref err) => write!(f, "{}", err),
+            MapError::SizeMismatch { expected, found } => {
+                write!(f, "expected {} blocks, found {}", expected, found)
+            }
+            MapError::OutOfBounds { x, y } => write!(f, "({}, {}) is outside the map", x, y),
+            MapError::UnknownBlock { x, y, block, count } => write!(
+                f,
+                "block {:#04x} at ({}, {}) is past the end of the blockset ({} blocks)",
+                block, x, y, count
+            ),
+        }
+    }
+}
+
+impl Error for MapError {}
+
+impl From<io::Error> for MapError {
+    fn from(err: io::Error) -> Self {
+        MapError::Io(err)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Snapshot {
+    width: u8,
+    height: u8,
+    blocks: Vec<u8>,
+}
+
+/// One edit of a `Map`, holding what it takes to undo and redo it
+#[derive(Clone, Debug)]
+enum MapEdit {
+    /// Cells that changed: index, block before and block after
+    Cells(Vec<(usize, u8, u8)>),
+    /// A resize moves every cell, so the whole grid is kept on both sides
+    Resize { before: Snapshot, after: Snapshot },
+    /// Blocks of the blockset that changed, to undo and to redo
+    Blockset {
+        undo: Vec<(u8, Option<Block>)>,
+        redo: Vec<(u8, Option<Block>)>,
+    },
+}
+
+impl MapEdit {
+    /// Label of a step made of `edits`, going by the first of them
+    fn describe(edits: &[MapEdit]) -> String {
+        match edits.first() {
+            Some(&MapEdit::Cells(_)) => {
+                let count: usize = edits
+                    .iter()
+                    .map(|edit| match *edit {
+                        MapEdit::Cells(ref cells) => cells.len(),
+                        _ => 0,
+                    })
+                    .sum();
+                match count {
+                    1 => "Paint 1 block".to_string(),
+                    count => format!("Paint {} blocks", count),
+                }
+            }
+            Some(MapEdit::Resize { after, .. }) => {
+                format!("Resize to {}x{}", after.width, after.height)
+            }
+            Some(&MapEdit::Blockset { .. }) => "Edit blockset".to_string(),
+            None => String::new(),
+        }
+    }
+}
+
+impl Cost for MapEdit {
+    fn cost(&self) -> usize {
+        match *self {
+            MapEdit::Cells(ref cells) => cells.len() * size_of::<(usize, u8, u8)>(),
+            MapEdit::Resize { ref before, ref after } => before.blocks.len() + after.blocks.len(),
+            MapEdit::Blockset { ref undo, ref redo } => {
+                (undo.len() + redo.len()) * size_of::<(u8, Option<Block>)>()
+            }
+        }
+    }
+}
+
+/// A map document: a `width` x `height` grid of block ids plus its undo history.
+///
+/// Edits are bounds-checked and queued as `MapChange`s, which views collect
+/// with `take_changes`.
+#[derive(Clone, Debug)]
+pub struct Map {
+    width: u8,
+    height: u8,
+    blocks: Vec<u8>,
+    history: History<MapEdit>,
+    /// Cells edited since the last commit: block before the first edit, and block now
+    pending_cells: BTreeMap<usize, (u8, u8)>,
+    /// Other edits made since the last commit, oldest first
+    pending: Vec<MapEdit>,
+    /// Bumped whenever the history changes, so views only refresh it then
+    history_revision: usize,
+    changes: Vec<MapChange>,
+}
+
+impl Map {
+    pub fn new(width: u8, height: u8, fill: u8) -> Self {
+        let blocks = vec![fill; width as usize * height as usize];
+        Map {
+            width: width,
+            height: height,
+            blocks: blocks,
+            history: History::new(Limit::default()),
+            pending_cells: BTreeMap::new(),
+            pending: Vec::new(),
+            history_revision: 0,
+            changes: Vec::new(),
+        }
+    }
+
+    pub fn from_bytes(width: u8, height: u8, blocks: Vec<u8>) -> Result<Self, MapError> {
+        let expected = width as usize * height as usize;
+        if blocks.len() != expected {
+            return Err(MapError::SizeMismatch {
+                expected: expected,
+                found: blocks.len(),
+            });
+        }
+
+        Ok(Map {
+            width: width,
+            height: height,
+            blocks: blocks,
+            history: History::new(Limit::default()),
+            pending_cells: BTreeMap::new(),
+            pending: Vec::new(),
+            history_revision: 0,
+            changes: Vec::new(),
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, width: u8, height: u8) -> Result<Self, MapError> {
+        let mut blocks = Vec::new();
+        File::open(path)?.read_to_end(&mut blocks)?;
+        Self::from_bytes(width, height, blocks)
+    }
+
+    /// Check that every block id is below `count`, the length of the blockset
+    pub fn check_blocks(&self, count: usize) -> Result<(), MapError> {
+        match self.blocks.iter().position(|&block| block as usize >= count) {
+            Some(index) => {
+                let (x, y) = self.position(index);
+                Err(MapError::UnknownBlock {
+                    x: x as usize,
+                    y: y as usize,
+                    block: self.blocks[index],
+                    count: count,
+                })
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        File::create(path)?.write_all(&self.blocks)
+    }
+
+    pub fn width(&self) -> u8 {
+        self.width
+    }
+
+    pub fn height(&self) -> u8 {
+        self.height
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.blocks
+    }
+
+    pub fn index(&self, x: u8, y: u8) -> Option<usize> {
+        if x < self.width && y < self.height {
+            Some(x as usize + y as usize * self.width as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn position(&self, index: usize) -> (u8, u8) {
+        let width = self.width as usize;
+        ((index % width) as u8, (index / width) as u8)
+    }
+
+    pub fn get(&self, x: u8, y: u8) -> Option<u8> {
+        self.index(x, y).map(|index| self.blocks[index])
+    }
+
+    pub fn get_at(&self, index: usize) -> Option<u8> {
+        self.blocks.get(index).cloned()
+    }
+
+    /// Set the block at `(x, y)`, returning the block it replaced
+    pub fn set(&mut self, x: u8, y: u8, block: u8) -> Result<u8, MapError> {
+        match self.index(x, y) {
+            Some(index) => self.set_at(index, block),
+            None => Err(MapError::OutOfBounds {
+                x: x as usize,
+                y: y as usize,
+            }),
+        }
+    }
+
+    pub fn set_at(&mut self, index: usize, block: u8) -> Result<u8, MapError> {
+        if index >= self.blocks.len() {
+            let width = self.width as usize;
+            return Err(MapError::OutOfBounds {
+                x: index % width.max(1),
+                y: index / width.max(1),
+            });
+        }
+
+        let old = self.blocks[index];
+        if old != block {
+            self.pending_cells.entry(index).or_insert((old, block)).1 = block;
+            self.put(index, block);
+        }
+        Ok(old)
+    }
+
+    /// Set every cell of `rect` that lies within the map
+    pub fn fill_rect(&mut self, rect: Rect, block: u8) {
+        for (x, y) in rect.positions() {
+            let _ = self.set(x, y, block);
+        }
+    }
+
+    /// Move the blocks of `rect` so its top left corner lands on `(x, y)`,
+    /// leaving `fill` behind; blocks moved off the map are dropped
+    pub fn move_rect(&mut self, rect: Rect, x: u8, y: u8, fill: u8) {
+        let blocks: Vec<((u8, u8), Option<u8>)> = rect.positions()
+            .into_iter()
+            .map(|(bx, by)| ((bx - rect.x, by - rect.y), self.get(bx, by)))
+            .collect();
+
+        self.fill_rect(rect, fill);
+        for ((dx, dy), block) in blocks {
+            if let (Some(bx), Some(by), Some(block)) = (x.checked_add(dx), y.checked_add(dy), block) {
+                let _ = self.set(bx, by, block);
+            }
+        }
+    }
+
+    /// Replace the 4-connected area of identical blocks around `(x, y)` with
+    /// `block`, staying inside `within` if given. Returns how many cells changed.
+    pub fn flood_fill(&mut self, x: u8, y: u8, block: u8, within: Option<Rect>) -> usize {
+        let inside = |x: u8, y: u8| within.is_none_or(|rect| rect.contains(x, y));
+        let target = match self.get(x, y) {
+            Some(target) if target != block && inside(x, y) => target,
+            _ => return 0,
+        };
+
+        let mut count = 0;
+        let mut stack = vec![(x, y)];
+        while let Some((x, y)) = stack.pop() {
+            if self.get(x, y) != Some(target) || !inside(x, y) {
+                continue;
+            }
+            let _ = self.set(x, y, block);
+            count += 1;
+
+            if x > 0 {
+                stack.push((x - 1, y));
+            }
+            if y > 0 {
+                stack.push((x, y - 1));
+            }
+            if x < self.width - 1 {
+                stack.push((x + 1, y));
+            }
+            if y < self.height - 1 {
+                stack.push((x, y + 1));
+            }
+        }
+        count
+    }
+
+    /// Copy the part of `rect` that lies within the map
+    pub fn copy_region(&self, rect: Rect) -> Region {
+        let x1 = min(rect.x as usize + rect.width as usize, self.width as usize);
+        let y1 = min(rect.y as usize + rect.height as usize, self.height as usize);
+        let width = x1.saturating_sub(rect.x as usize);
+        let height = y1.saturating_sub(rect.y as usize);
+
+        let mut blocks = Vec::with_capacity(width * height);
+        for y in rect.y as usize..y1 {
+            for x in rect.x as usize..x1 {
+                blocks.push(self.blocks[x + y * self.width as usize]);
+            }
+        }
+
+        Region {
+            width: width as u8,
+            height: height as u8,
+            blocks: blocks,
+        }
+    }
+
+    /// Paste `region` with its top left corner at `(x, y)`, dropping what
+    /// falls outside the map (the corner itself may lie left of or above it)
+    pub fn paste_region(&mut self, x: i32, y: i32, region: &Region) {
+        for ry in 0..region.height {
+            for rx in 0..region.width {
+                let (map_x, map_y) = (x + rx as i32, y + ry as i32);
+                if map_x >= 0 && map_y >= 0 && map_x < self.width as i32 &&
+                    map_y < self.height as i32
+                {
+                    let _ = self.set(map_x as u8, map_y as u8, region.get(rx, ry));
+                }
+            }
+        }
+    }
+
+    /// Change the dimensions, keeping the blocks at `anchor` in place and
+    /// filling new cells with `fill`
+    pub fn resize(&mut self, width: u8, height: u8, anchor: Anchor, fill: u8) {
+        let (weight_x, weight_y) = anchor.weights();
+        let offset_x = (width as i32 - self.width as i32) * weight_x / 2;
+        let offset_y = (height as i32 - self.height as i32) * weight_y / 2;
+
+        let mut blocks = Vec::with_capacity(width as usize * height as usize);
+        for y in 0..height as i32 {
+            for x in 0..width as i32 {
+                let (old_x, old_y) = (x - offset_x, y - offset_y);
+                let inside = old_x >= 0 && old_x < self.width as i32 && old_y >= 0 &&
+                    old_y < self.height as i32;
+                blocks.push(if inside {
+                    self.blocks[old_x as usize + old_y as usize * self.width as usize]
+                } else {
+                    fill
+                });
+            }
+        }
+
+        self.flush_cells();
+        let before = self.snapshot();
+        let after = Snapshot {
+            width: width,
+            height: height,
+            blocks: blocks,
+        };
+        self.replace(after.clone());
+        self.pending.push(MapEdit::Resize {
+            before: before,
+            after: after,
+        });
+    }
+
+    /// Record a change of the blockset the map is drawn with, so that it is
+    /// undone along with the map. Returns the ids of the blocks that changed.
+    pub fn record_blockset(&mut self, before: &Blockset, after: &Blockset) -> Vec<u8> {
+        let redo = before.changes_to(after);
+        if redo.is_empty() {
+            return Vec::new();
+        }
+
+        self.flush_cells();
+        let ids = redo.iter().map(|&(id, _)| id).collect();
+        self.pending.push(MapEdit::Blockset {
+            undo: after.changes_to(before),
+            redo: redo,
+        });
+        ids
+    }
+
+    /// Record the edits made since the last commit as one undoable step,
+    /// labelled after what they did
+    pub fn commit(&mut self) {
+        self.flush_cells();
+        let label = MapEdit::describe(&self.pending);
+        self.commit_as(&label);
+    }
+
+    /// Record the edits made since the last commit as one undoable step called `label`
+    pub fn commit_as(&mut self, label: &str) {
+        self.flush_cells();
+        if self.pending.is_empty() {
+            return;
+        }
+        let step = self.pending.drain(..).collect();
+        self.history.push(label, step);
+        self.history_revision += 1;
+    }
+
+    /// Every step of the history, abandoned branches included
+    pub fn history_tree(&self) -> Vec<Entry> {
+        self.history.tree()
+    }
+
+    /// Changes whenever a step is added, undone, redone or forgotten
+    pub fn history_revision(&self) -> usize {
+        self.history_revision
+    }
+
+    /// Undo and redo, switching branches if need be, until step `id` of
+    /// `history_tree` is the present. Returns whether anything changed.
+    pub fn jump_to(&mut self, id: usize) -> bool {
+        self.commit();
+        if !self.history.contains(id) {
+            return false;
+        }
+
+        let mut moved = false;
+        while !self.history.is_ancestor(self.history.current(), id) && self.undo() {
+            moved = true;
+        }
+        self.history.activate(id);
+        while self.history.current() != id && self.redo() {
+            moved = true;
+        }
+        moved
+    }
+
+    /// Make the steps committed until the matching `end_group` a single step
+    pub fn begin_group(&mut self) {
+        self.commit();
+        self.history.begin_group();
+    }
+
+    pub fn end_group(&mut self) {
+        self.commit();
+        self.history.end_group();
+    }
+
+    pub fn history_limit(&self) -> Limit {
+        self.history.limit()
+    }
+
+    pub fn set_history_limit(&mut self, limit: Limit) {
+        self.history.set_limit(limit);
+        self.history_revision += 1;
+    }
+
+    /// Revert the last step, after committing the edits made since the last commit
+    pub fn undo(&mut self) -> bool {
+        self.commit();
+        match self.history.undo() {
+            Some(step) => {
+                for edit in step.into_iter().rev() {
+                    self.revert(edit);
+                }
+                self.history_revision += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn redo(&mut self) -> bool {
+        self.commit();
+        match self.history.redo() {
+            Some(step) => {
+                for edit in step {
+                    self.apply(edit);
+                }
+                self.history_revision += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn revert(&mut self, edit: MapEdit) {
+        match edit {
+            MapEdit::Cells(cells) => for (index, before, _) in cells {
+                self.put(index, before);
+            },
+            MapEdit::Resize { before, .. } => self.replace(before),
+            MapEdit::Blockset { undo, .. } => self.changes.push(MapChange::Blockset(undo)),
+        }
+    }
+
+    fn apply(&mut self, edit: MapEdit) {
+        match edit {
+            MapEdit::Cells(cells) => for (index, _, after) in cells {
+                self.put(index, after);
+            },
+            MapEdit::Resize { after, .. } => self.replace(after),
+            MapEdit::Blockset { redo, .. } => self.changes.push(MapChange::Blockset(redo)),
+        }
+    }
+
+    /// Turn the cells edited since the last commit into an edit, after the other ones
+    fn flush_cells(&mut self) {
+        let cells: Vec<_> = self.pending_cells
+            .iter()
+            .filter(|&(_, &(before, after))| before != after)
+            .map(|(&index, &(before, after))| (index, before, after))
+            .collect();
+        self.pending_cells.clear();
+        if !cells.is_empty() {
+            self.pending.push(MapEdit::Cells(cells));
+        }
+    }
+
+    /// Set a cell without recording it in the history
+    fn put(&mut self, index: usize, block: u8) {
+        self.blocks[index] = block;
+        self.changes.push(MapChange::Block {
+            index: index,
+            block: block,
+        });
+    }
+
+    /// Drain the changes made since the last call
+    pub fn take_changes(&mut self) -> Vec<MapChange> {
+        self.changes.drain(..).collect()
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            width: self.width,
+            height: self.height,
+            blocks: self.blocks.clone(),
+        }
+    }
+
+    fn replace(&mut self, state: Snapshot) {
+        if state.width != self.width || state.height != self.height {
+            self.width = state.width;
+            self.height = state.height;
+            self.changes.push(MapChange::Resized {
+                width: state.width,
+                height: state.height,
+            });
+        } else {
+            for (index, block) in Self::diff(&self.blocks, &state.blocks) {
+                self.changes.push(MapChange::Block {
+                    index: index,
+                    block: block,
+                });
+            }
+        }
+        self.blocks = state.blocks;
+    }
+
+    /// Find which bytes changed
+    fn diff(old: &[u8], new: &[u8]) -> Vec<(usize, u8)> {
+        let mut result = Vec::new();
+
+        for (i, (o, n)) in old.iter().zip(new).enumerate() {
+            if o != n {
+                result.push((i, *n));
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn from_bytes_checks_size() {
+        assert!(Map::from_bytes(2, 2, vec![0; 4]).is_ok());
+        match Map::from_bytes(2, 2, vec![0; 3]) {
+            Err(MapError::SizeMismatch { expected: 4, found: 3 }) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn check_blocks_finds_unknown_ids() {
+        let map = Map::from_bytes(2, 2, vec![0, 1, 2, 5]).unwrap();
+        assert!(map.check_blocks(6).is_ok());
+        match map.check_blocks(4) {
+            Err(MapError::UnknownBlock { x: 1, y: 1, block: 5, count: 4 }) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn set_is_bounds_checked() {
+        let mut map = Map::new(3, 2, 0);
+        assert_eq!(map.set(2, 1, 7).unwrap(), 0);
+        assert_eq!(map.get(2, 1), Some(7));
+        assert!(map.set(3, 0, 1).is_err());
+        assert!(map.set_at(6, 1).is_err());
+        assert_eq!(map.get(0, 2), None);
+    }
+
+    #[test]
+    fn edits_are_reported_once() {
+        let mut map = Map::new(2, 2, 0);
+        map.set(1, 1, 5).unwrap();
+        map.set(0, 0, 0).unwrap();
+        assert_eq!(map.take_changes(), vec![MapChange::Block { index: 3, block: 5 }]);
+        assert!(map.take_changes().is_empty());
+    }
+
+    #[test]
+    fn undo_and_redo_report_changes() {
+        let mut map = Map::new(2, 1, 0);
+        map.set(0, 0, 1).unwrap();
+        map.commit();
+        map.set(1, 0, 2).unwrap();
+        map.commit();
+        map.take_changes();
+
+        assert!(map.undo());
+        assert_eq!(map.as_bytes(), &[1, 0]);
+        assert_eq!(map.take_changes(), vec![MapChange::Block { index: 1, block: 0 }]);
+
+        assert!(map.redo());
+        assert_eq!(map.as_bytes(), &[1, 2]);
+    }
+
+    #[test]
+    fn commit_groups_edits() {
+        let mut map = Map::new(3, 1, 0);
+        map.set(0, 0, 1).unwrap();
+        map.set(1, 0, 1).unwrap();
+        map.commit();
+        map.set(2, 0, 0).unwrap();
+        map.commit();
+
+        assert!(map.undo());
+        assert_eq!(map.as_bytes(), &[0, 0, 0]);
+    }
+
+    #[test]
+    fn rect_from_any_corners() {
+        let rect = Rect::from_corners((3, 1), (1, 2));
+        assert_eq!(
+            rect,
+            Rect {
+                x: 1,
+                y: 1,
+                width: 3,
+                height: 2,
+            }
+        );
+        assert!(rect.contains(3, 2));
+        assert!(!rect.contains(0, 1));
+        assert_eq!(rect.positions()[..4], [(1, 1), (2, 1), (3, 1), (1, 2)]);
+    }
+
+    #[test]
+    fn rect_outline() {
+        let rect = Rect::from_corners((0, 0), (2, 2));
+        assert_eq!(rect.outline().len(), 8);
+        assert!(!rect.outline().contains(&(1, 1)));
+        assert_eq!(Rect::from_corners((4, 4), (4, 4)).outline(), vec![(4, 4)]);
+    }
+
+    #[test]
+    fn line_cells() {
+        assert_eq!(line((0, 0), (3, 1)), vec![(0, 0), (1, 0), (2, 1), (3, 1)]);
+        assert_eq!(line((2, 3), (2, 0)), vec![(2, 3), (2, 2), (2, 1), (2, 0)]);
+        assert_eq!(line((1, 1), (1, 1)), vec![(1, 1)]);
+    }
+
+    #[test]
+    fn fill_rect_is_clipped() {
+        let mut map = Map::new(3, 2, 0);
+        map.fill_rect(Rect::from_corners((1, 1), (5, 5)), 4);
+        assert_eq!(map.as_bytes(), &[0, 0, 0, 0, 4, 4]);
+    }
+
+    #[test]
+    fn move_rect_leaves_fill_behind() {
+        let mut map = Map::from_bytes(3, 2, vec![1, 2, 0, 3, 4, 0]).unwrap();
+        map.move_rect(Rect::from_corners((0, 0), (1, 1)), 1, 0, 9);
+        assert_eq!(map.as_bytes(), &[9, 1, 2, 9, 3, 4]);
+        map.move_rect(Rect::from_corners((1, 0), (2, 0)), 2, 1, 9);
+        assert_eq!(map.as_bytes(), &[9, 9, 9, 9, 3, 1]);
+    }
+
+    #[test]
+    fn flood_fill_stays_connected() {
+        let mut map = Map::from_bytes(4, 3, vec![
+            0, 0, 1, 0,
+            1, 0, 1, 0,
+            0, 0, 1, 1,
+        ]).unwrap();
+        assert_eq!(map.flood_fill(1, 1, 2, None), 5);
+        assert_eq!(map.as_bytes(), &[2, 2, 1, 0, 1, 2, 1, 0, 2, 2, 1, 1]);
+    }
+
+    #[test]
+    fn flood_fill_within_selection() {
+        let mut map = Map::new(3, 3, 0);
+        let rect = Rect::from_corners((1, 1), (2, 2));
+        assert_eq!(map.flood_fill(0, 0, 1, Some(rect)), 0);
+        assert_eq!(map.flood_fill(1, 1, 1, Some(rect)), 4);
+        assert_eq!(map.as_bytes(), &[0, 0, 0, 0, 1, 1, 0, 1, 1]);
+    }
+
+    #[test]
+    fn copy_and_paste_regions() {
+        let source = Map::from_bytes(3, 3, (0..9).collect()).unwrap();
+        let region = source.copy_region(Rect::from_corners((1, 1), (4, 4)));
+        assert_eq!((region.width, region.height), (2, 2));
+        assert_eq!(region.get(1, 0), 5);
+
+        let mut target = Map::new(3, 2, 0);
+        target.paste_region(2, 0, &region);
+        assert_eq!(target.as_bytes(), &[0, 0, 4, 0, 0, 7]);
+
+        let mut target = Map::new(2, 2, 0);
+        target.paste_region(-1, -1, &Region::new(2, 2, vec![1, 2, 3, 4]));
+        assert_eq!(target.as_bytes(), &[4, 0, 0, 0]);
+    }
+
+    #[test]
+    fn resize_keeps_anchored_blocks() {
+        let mut map = Map::from_bytes(2, 2, vec![1, 2, 3, 4]).unwrap();
+        map.resize(4, 3, Anchor::BottomRight, 0);
+        assert_eq!(map.width(), 4);
+        assert_eq!(map.as_bytes(), &[0, 0, 0, 0, 0, 0, 1, 2, 0, 0, 3, 4]);
+
+        let mut map = Map::from_bytes(3, 3, (0..9).collect()).unwrap();
+        map.resize(1, 1, Anchor::Center, 0);
+        assert_eq!(map.as_bytes(), &[4]);
+    }
+
+    #[test]
+    fn resize_can_be_undone() {
+        let mut map = Map::new(2, 2, 1);
+        map.resize(3, 1, Anchor::TopLeft, 9);
+        map.commit();
+        assert_eq!(
+            map.take_changes(),
+            vec![MapChange::Resized { width: 3, height: 1 }]
+        );
+
+        assert!(map.undo());
+        assert_eq!((map.width(), map.height()), (2, 2));
+        assert_eq!(map.as_bytes(), &[1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn groups_commits_into_one_step() {
+        let mut map = Map::new(3, 1, 0);
+        map.begin_group();
+        map.set(0, 0, 1).unwrap();
+        map.commit();
+        map.resize(4, 1, Anchor::TopLeft, 2);
+        map.commit();
+        map.end_group();
+        map.set(1, 0, 3).unwrap();
+        map.commit();
+
+        assert!(map.undo());
+        assert_eq!(map.as_bytes(), &[1, 0, 0, 2]);
+        assert!(map.undo());
+        assert_eq!(map.as_bytes(), &[0, 0, 0]);
+        assert!(!map.undo());
+        assert!(map.redo());
+        assert_eq!(map.as_bytes(), &[1, 0, 0, 2]);
+    }
+
+    #[test]
+    fn blockset_changes_are_undone_with_the_map() {
+        let before = Blockset::from_bytes(&[0; 16], 1).unwrap();
+        let mut after = before.clone();
+        after.push(Block::new([1; 16]));
+
+        let mut map = Map::new(1, 1, 0);
+        assert_eq!(map.record_blockset(&before, &after), vec![1]);
+        map.set(0, 0, 1).unwrap();
+        map.commit();
+        map.take_changes();
+
+        assert!(map.undo());
+        assert_eq!(
+            map.take_changes(),
+            vec![
+                MapChange::Block { index: 0, block: 0 },
+                MapChange::Blockset(vec![(1, None)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn history_keeps_to_its_limit() {
+        let mut map = Map::new(1, 1, 0);
+        map.set_history_limit(Limit {
+            steps: Some(2),
+            cost: None,
+        });
+        for block in 1..5 {
+            map.set(0, 0, block).unwrap();
+            map.commit();
+        }
+
+        assert!(map.undo());
+        assert!(map.undo());
+        assert!(!map.undo());
+        assert_eq!(map.get(0, 0), Some(2));
+    }
+
+    #[test]
+    fn jumps_through_labelled_steps() {
+        let mut map = Map::new(2, 1, 0);
+        map.set(0, 0, 1).unwrap();
+        map.set(1, 0, 1).unwrap();
+        map.commit();
+        map.flood_fill(0, 0, 2, None);
+        map.commit_as("Fill");
+        map.resize(1, 1, Anchor::TopLeft, 0);
+        map.commit();
+        let labels: Vec<String> = map.history_tree().into_iter().map(|entry| entry.label).collect();
+        assert_eq!(labels, vec!["", "Paint 2 blocks", "Fill", "Resize to 1x1"]);
+
+        assert!(map.jump_to(1));
+        assert!(map.history_tree()[1].current);
+        assert_eq!(map.as_bytes(), &[1, 1]);
+        assert!(map.jump_to(3));
+        assert_eq!(map.as_bytes(), &[2]);
+        assert!(!map.jump_to(3));
+    }
+
+    #[test]
+    fn history_revision_follows_the_steps() {
+        let mut map = Map::new(2, 1, 0);
+        let start = map.history_revision();
+        map.commit();
+        assert_eq!(map.history_revision(), start);
+
+        map.set(0, 0, 1).unwrap();
+        map.commit();
+        let painted = map.history_revision();
+        assert!(painted != start);
+
+        assert!(map.undo());
+        assert!(map.history_revision() != painted);
+        let undone = map.history_revision();
+        assert!(!map.undo());
+        assert_eq!(map.history_revision(), undone);
+    }
+
+    #[test]
+    fn jumps_between_branches() {
+        let mut map = Map::new(2, 1, 0);
+        map.set(0, 0, 1).unwrap();
+        map.commit();
+        map.set(1, 0, 2).unwrap();
+        map.commit();
+        map.undo();
+        map.set(1, 0, 3).unwrap();
+        map.commit();
+        assert_eq!(map.as_bytes(), &[1, 3]);
+
+        assert!(map.jump_to(2));
+        assert_eq!(map.as_bytes(), &[1, 2]);
+        assert!(map.undo());
+        assert!(map.redo());
+        assert_eq!(map.as_bytes(), &[1, 2]);
+        assert!(map.jump_to(3));
+        assert_eq!(map.as_bytes(), &[1, 3]);
+    }
+}
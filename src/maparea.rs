@@ -6,78 +6,409 @@ use gdk_pixbuf::Pixbuf;
 use cairo;
 use cairo::Context;
 
-use std::cmp::{min, max};
+use std::cmp::min;
 use std::cell::Ref;
 use std::cell::RefCell;
+use std::fmt;
 use std::rc::Rc;
-use std::mem;
 
+use blockset::Blockset;
+use cgb::BgPalettes;
+use config::OverlayColors;
 use constants::*;
+use history::{Entry, Limit};
+use map::{Anchor, Map, MapChange, Rect, Region};
 use tileset::Tileset;
-use palette::change_palette;
-use history::History;
+use position::{scroll_zoom, set_source_pixbuf_sharp, zoom_around, Positionable, ZOOM_LEVELS};
+use tools::{Eyedropper, Pencil, Tool, ToolContext};
+
+/// Guides drawn over the map, which never change `pix_cache`
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Overlays {
+    pub block_grid: bool,
+    pub tile_grid: bool,
+    /// The 160x144 screen, as the player would see it standing under the cursor
+    pub screen: bool,
+    /// Where the player can stand without the screen going past the map edges
+    pub camera: bool,
+}
+
+/// Told about the history of the map whenever it may have changed
+pub struct HistoryListener(Box<dyn Fn(&[Entry])>);
 
-#[derive(Clone, Debug)]
+impl fmt::Debug for HistoryListener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("HistoryListener")
+    }
+}
+
+#[derive(Debug)]
 pub struct Maparea {
-    mapset: Vec<u8>,
+    map: Map,
     tileset: Rc<RefCell<Tileset>>,
-    width: u8,
-    height: u8,
     hovered: Option<usize>,
+    /// Handles the left button
+    tool: Box<dyn Tool>,
+    /// Handles the right button, whatever the tool
+    picker: Eyedropper,
+    /// Button being held down on the map, if any
+    pressed: Option<u32>,
+    selection: Option<Rect>,
+    /// Block left behind by cutting, deleting or moving a selection
+    background: u8,
+    floating: Option<Region>,
     pix_cache: Pixbuf,
-    palette: RgbPalette,
+    scale: f64,
+    overlays: Overlays,
+    overlay_colors: OverlayColors,
+    /// Pointer position in unzoomed map pixels
+    cursor: Option<(f64, f64)>,
+    history_listener: Option<HistoryListener>,
+    /// `history_revision` of the map when the listener was last told, if it was
+    history_told: Option<usize>,
     pub widget: DrawingArea,
-    history: History,
+}
+
+impl Positionable for Maparea {
+    fn scale(&self) -> f64 {
+        self.scale
+    }
 }
 
 impl Maparea {
-    pub fn new(
-        widget: DrawingArea,
-        width: u8,
-        height: u8,
-        mapset: Vec<u8>,
-        tileset: Rc<RefCell<Tileset>>,
-    ) -> Self {
+    pub fn new(widget: DrawingArea, map: Map, tileset: Rc<RefCell<Tileset>>) -> Self {
         let pix_cache = Self::static_build_pix(
-            width as i32 * BLOCK_SIZE as i32,
-            height as i32 * BLOCK_SIZE as i32,
-            &mapset,
+            Self::block_to_pixel_size(map.width()),
+            Self::block_to_pixel_size(map.height()),
+            map.as_bytes(),
             &*tileset.borrow(),
         );
 
-        let history = History::new(mapset.clone());
-
         Maparea {
-            mapset: mapset,
+            map: map,
             tileset: tileset,
-            width: width,
-            height: height,
             hovered: None,
+            tool: Box::new(Pencil::default()),
+            picker: Eyedropper::default(),
+            pressed: None,
+            selection: None,
+            background: 0,
+            floating: None,
             pix_cache: pix_cache,
-            palette: BASE_PALETTE,
+            scale: 1.,
+            overlays: Overlays::default(),
+            overlay_colors: OverlayColors::default(),
+            cursor: None,
+            history_listener: None,
+            history_told: None,
             widget: widget,
-            history: history,
         }
     }
 
-    pub fn on_bytes<T, F: Fn(&[u8]) -> T>(&self, call_on_bytes: F) -> T {
-        call_on_bytes(&self.mapset)
+    pub fn map(&self) -> &Map {
+        &self.map
+    }
+
+    pub fn tileset(&self) -> Rc<RefCell<Tileset>> {
+        self.tileset.clone()
+    }
+
+    /// The map as drawn, without selection or overlays
+    pub fn pix(&self) -> &Pixbuf {
+        &self.pix_cache
+    }
+
+    /// Coordinates of the block under the pointer
+    pub fn hovered_position(&self) -> Option<(u8, u8)> {
+        self.hovered.map(|index| self.map.position(index))
+    }
+
+    pub fn set_tool(&mut self, tool: Box<dyn Tool>) {
+        /* a stroke cut short is still a step */
+        self.cancel_tools();
+        self.sync();
+        self.tool = tool;
+        self.cancel_paste();
+        self.update_cursor();
+        self.widget.queue_draw();
+    }
+
+    /// Tool the mouse is currently driving
+    fn active_tool(&self) -> &dyn Tool {
+        match self.pressed {
+            Some(3) => &self.picker,
+            _ => &*self.tool,
+        }
+    }
+
+    /// Run a tool hook, then show whatever it changed
+    fn with_tool<F: FnOnce(&mut dyn Tool, &mut ToolContext)>(&mut self, picker: bool, f: F) {
+        {
+            let mut ctx = ToolContext {
+                map: &mut self.map,
+                tileset: &self.tileset,
+                selection: &mut self.selection,
+                background: self.background,
+            };
+            let tool: &mut dyn Tool = if picker {
+                &mut self.picker
+            } else {
+                &mut *self.tool
+            };
+            f(tool, &mut ctx);
+        }
+        self.sync();
+        self.widget.queue_draw();
+    }
+
+    fn cancel_tools(&mut self) {
+        self.pressed = None;
+        let mut ctx = ToolContext {
+            map: &mut self.map,
+            tileset: &self.tileset,
+            selection: &mut self.selection,
+            background: self.background,
+        };
+        self.tool.cancel(&mut ctx);
+        self.picker.cancel(&mut ctx);
+    }
+
+    fn update_cursor(&self) {
+        if let Some(window) = self.widget.get_window() {
+            let cursor = gdk::Cursor::new_for_display(&window.get_display(), self.tool.cursor());
+            window.set_cursor(Some(&cursor));
+        }
+    }
+
+    pub fn background(&self) -> u8 {
+        self.background
+    }
+
+    pub fn set_background(&mut self, block: u8) {
+        self.background = block;
+    }
+
+    pub fn copy(&self) -> Option<Region> {
+        self.selection.map(|rect| self.map.copy_region(rect))
+    }
+
+    /// Copy the selection, then clear it to the background block
+    pub fn cut(&mut self) -> Option<Region> {
+        let region = self.copy();
+        let background = self.background;
+        self.fill_selection_as(background, "Cut");
+        region
+    }
+
+    /// Float `region` under the cursor until the next left click pastes it
+    pub fn start_paste(&mut self, region: Region) {
+        self.floating = Some(region);
+        self.widget.queue_draw();
+    }
+
+    pub fn cancel_paste(&mut self) {
+        if self.floating.take().is_some() {
+            self.widget.queue_draw();
+        }
+    }
+
+    fn finish_paste(&mut self, (x, y): (u8, u8)) {
+        if let Some(region) = self.floating.take() {
+            self.map.paste_region(x as i32, y as i32, &region);
+            self.map.commit_as(&format!("Paste {}x{}", region.width, region.height));
+            self.sync();
+            self.widget.queue_draw();
+        }
+    }
+
+    pub fn select(&mut self, selection: Option<Rect>) {
+        self.selection = selection;
+        self.widget.queue_draw();
+    }
+
+    pub fn select_all(&mut self) {
+        let (width, height) = (self.map.width(), self.map.height());
+        if width > 0 && height > 0 {
+            self.select(Some(Rect::from_corners((0, 0), (width - 1, height - 1))));
+        }
+    }
+
+    pub fn fill_selection(&mut self, block: u8) {
+        self.fill_selection_as(block, "Fill selection");
+    }
+
+    fn fill_selection_as(&mut self, block: u8, label: &str) {
+        if let Some(rect) = self.selection {
+            self.map.fill_rect(rect, block);
+            self.map.commit_as(label);
+            self.sync();
+        }
+    }
+
+    /// Clear the selection to the background block
+    pub fn delete_selection(&mut self) {
+        let background = self.background;
+        self.fill_selection_as(background, "Delete selection");
+    }
+
+    pub fn resize(&mut self, width: u8, height: u8, anchor: Anchor, fill: u8) {
+        self.map.resize(width, height, anchor, fill);
+        self.map.commit();
+        self.sync();
+    }
+
+    /// Change the blockset as one undoable step of the map's history
+    pub fn edit_blockset<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut Blockset) -> R,
+    {
+        let before = self.tileset.borrow().blockset().clone();
+        let result = self.tileset.borrow_mut().edit_blockset(f);
+        let changed = self.map.record_blockset(&before, self.tileset.borrow().blockset());
+        self.map.commit();
+        self.redraw_blocks(&changed);
+        self.sync();
+        result
+    }
+
+    /// How much undo history the map keeps, now and for maps shown later
+    pub fn set_history_limit(&mut self, limit: Limit) {
+        self.map.set_history_limit(limit);
+        self.sync();
+    }
+
+    /// Call `listener` with the history of the map after every change that
+    /// may have touched it: steps, undo, redo and other maps being shown
+    pub fn connect_history_changed<F: Fn(&[Entry]) + 'static>(&mut self, listener: F) {
+        listener(&self.map.history_tree());
+        self.history_listener = Some(HistoryListener(Box::new(listener)));
+        self.history_told = Some(self.map.history_revision());
+    }
+
+    /// Show another map in the same widget, keeping the connected events
+    pub fn set_map(&mut self, mut map: Map) {
+        map.set_history_limit(self.map.history_limit());
+        self.map = map;
+        self.hovered = None;
+        /* its history is another one, whatever its revision */
+        self.history_told = None;
+        self.rebuild();
+        self.sync();
+    }
+
+    /// Tool, hovered cell and selection, to be shown in the status bar
+    pub fn status(&self) -> String {
+        let tool = self.active_tool();
+        let mut parts = vec![tool.name().to_string()];
+        if let Some(index) = self.hovered {
+            parts.push(format!("{:?}", self.map.position(index)));
+        }
+        if let Some(status) = tool.status() {
+            parts.push(status);
+        }
+        if let Some(rect) = self.selection {
+            parts.push(format!(
+                "selection {}x{} at ({}, {})",
+                rect.width,
+                rect.height,
+                rect.x,
+                rect.y
+            ));
+        }
+        if self.scale != 1. {
+            parts.push(format!("{}%", (self.scale * 100.).round()));
+        }
+        parts.join(" - ")
+    }
+
+    /// Redraw the whole map, e.g. after its size or its tileset changed
+    pub fn rebuild(&mut self) {
+        let width = Self::block_to_pixel_size(self.map.width());
+        let height = Self::block_to_pixel_size(self.map.height());
+        self.map.take_changes();
+        self.cancel_tools();
+        self.selection = None;
+        self.floating = None;
+        self.pix_cache =
+            Self::static_build_pix(width, height, self.map.as_bytes(), &*self.tileset.borrow());
+        self.update_size_request();
+        self.widget.queue_draw();
+    }
+
+    /// Zoom the map, between the smallest and largest of `ZOOM_LEVELS`
+    pub fn set_scale(&mut self, scale: f64) {
+        let (first, last) = (ZOOM_LEVELS[0], ZOOM_LEVELS[ZOOM_LEVELS.len() - 1]);
+        self.scale = scale.max(first).min(last);
+        self.update_size_request();
+        self.widget.queue_draw();
+    }
+
+    pub fn overlays(&self) -> Overlays {
+        self.overlays
+    }
+
+    pub fn set_overlays(&mut self, overlays: Overlays) {
+        self.overlays = overlays;
+        self.widget.queue_draw();
+    }
+
+    pub fn set_overlay_colors(&mut self, colors: OverlayColors) {
+        self.overlay_colors = colors;
+        self.widget.queue_draw();
+    }
+
+    fn update_size_request(&self) {
+        let size = |blocks: u8| (Self::block_to_pixel_size(blocks) as f64 * self.scale).ceil() as i32;
+        self.widget.set_size_request(size(self.map.width()), size(self.map.height()));
+    }
+
+    /// Ctrl+scroll zooms around the cursor; returns whether the scroll was used
+    pub fn scroll(&mut self, ev: &gdk::EventScroll) -> bool {
+        match scroll_zoom(self.scale, ev) {
+            Some(scale) => {
+                let old_scale = self.scale;
+                self.set_scale(scale);
+                zoom_around(&self.widget, ev.get_position(), self.scale / old_scale);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Show the map and its tileset through another palette
+    pub fn set_palette(&mut self, palette: RgbPalette) {
+        self.tileset.borrow_mut().set_palette(palette);
+        self.redraw();
+    }
+
+    /// Show the map and its tileset through CGB palettes, or the display palette when `None`
+    pub fn set_bg_palettes(&mut self, bg_palettes: Option<BgPalettes>) {
+        self.tileset.borrow_mut().set_bg_palettes(bg_palettes);
+        self.redraw();
+    }
+
+    /// Repaint the whole map, after the look of every block changed
+    fn redraw(&mut self) {
+        let width = Self::block_to_pixel_size(self.map.width());
+        let height = Self::block_to_pixel_size(self.map.height());
+        self.pix_cache =
+            Self::static_build_pix(width, height, self.map.as_bytes(), &*self.tileset.borrow());
+        self.widget.queue_draw();
     }
 
     pub fn from_data(
         widget: DrawingArea,
-        width: u8,
-        height: u8,
-        mapset: Vec<u8>,
+        map: Map,
         tileset: Rc<RefCell<Tileset>>,
     ) -> Rc<RefCell<Option<Self>>> {
         widget.add_events(drawing_area_mask_bits!());
+        widget.add_events(gdk::EventMask::SCROLL_MASK.bits() as i32);
         widget.set_size_request(
-            width as i32 * BLOCK_SIZE as i32,
-            height as i32 * BLOCK_SIZE as i32,
+            Self::block_to_pixel_size(map.width()),
+            Self::block_to_pixel_size(map.height()),
         );
 
-        let maparea = Maparea::new(widget, width, height, mapset, tileset);
+        let maparea = Maparea::new(widget, map, tileset);
         let cell = Rc::new(RefCell::new(Some(maparea)));
 
         cell
@@ -98,57 +429,140 @@ impl Maparea {
                 Inhibit::default()
             }));
 
+            widget.connect_button_release_event(clone!(cell => move|el, ev| {
+                cell.borrow_mut().as_mut().map(|c| c.button_release(el, ev));
+                Inhibit::default()
+            }));
+
             widget.connect_draw(clone!(cell => move |_, context| {
                 cell.borrow().as_ref().map(|c| c.paint(&context));
                 Inhibit::default()
             }));
+
+            widget.connect_scroll_event(clone!(cell => move |_, ev| {
+                Inhibit(cell.borrow_mut().as_mut().map_or(false, |c| c.scroll(ev)))
+            }));
+
+            widget.connect_realize(clone!(cell => move |_| {
+                cell.borrow().as_ref().map(|c| c.update_cursor());
+            }));
         });
     }
 
-    fn set_mapset(&mut self, mapset: Vec<u8>) -> Vec<u8> {
-        let old_mapset = mem::replace(&mut self.mapset, mapset);
-        self.widget.queue_draw_area(
-            0,
-            0,
-            self.width as i32 * BLOCK_SIZE as i32,
-            self.height as i32 * BLOCK_SIZE as i32,
-        );
-        old_mapset
+    pub fn redo(&mut self) {
+        self.map.redo();
+        self.sync();
     }
 
-    fn replace_mapset(&mut self, state: Vec<u8>) {
-        for (index, block) in Maparea::diff(&self.mapset, &state) {
-            self.update_map_block(index, block);
-        }
-        self.set_mapset(state);
+    pub fn undo(&mut self) {
+        self.map.undo();
+        self.sync();
     }
 
-    pub fn redo(&mut self) {
-        self.history.redo().map(|state| self.replace_mapset(state));
+    /// Undo and redo in one go until step `id` of the history is the present
+    pub fn jump_to(&mut self, id: usize) {
+        self.map.jump_to(id);
+        self.sync();
     }
 
-    pub fn undo(&mut self) {
-        self.history.undo().map(|state| self.replace_mapset(state));
-    }
-
-    /// Find which bytes changed
-    /// # Example:
-    ///
-    /// ```
-    /// let old = vec![0, 1, 2];
-    /// let new = vec![0, 2, 2];
-    /// diff(old, new);
-    /// ```
-    fn diff(old: &[u8], new: &[u8]) -> Vec<(usize, u8)> {
-        let mut result = Vec::new();
-
-        for (i, (o, n)) in old.iter().zip(new).enumerate() {
-            if o != n {
-                result.push((i, *n));
+    /// Repaint every cell showing one of `blocks`, after the blocks themselves changed
+    pub fn redraw_blocks(&mut self, blocks: &[u8]) {
+        let width = Self::block_to_pixel_size(self.map.width());
+        let height = Self::block_to_pixel_size(self.map.height());
+
+        let pix_cache = Self::new_pixbuf_static(width, height, |context: &Context| {
+            context.set_source_pixbuf(&self.pix_cache, 0., 0.);
+            context.paint();
+
+            let tileset = self.tileset.borrow();
+            for (index, &block) in self.map.as_bytes().iter().enumerate() {
+                if blocks.contains(&block) {
+                    let (x, y) = self.coords(index);
+                    tileset.get_tile_pix(block).map(|tile| {
+                        context.set_source_pixbuf(&tile, x as f64, y as f64);
+                        context.paint();
+                    });
+                }
+            }
+        });
+        self.pix_cache = pix_cache;
+        self.widget.queue_draw();
+    }
+
+    /// Bring the pixel cache and the widget up to date with the map. Every
+    /// edit of the map goes through here, so this is where the history is told.
+    fn sync(&mut self) {
+        let revision = self.map.history_revision();
+        if self.history_told != Some(revision) {
+            if let Some(HistoryListener(ref listener)) = self.history_listener {
+                listener(&self.map.history_tree());
             }
+            self.history_told = Some(revision);
         }
 
-        result
+        let changes = self.map.take_changes();
+        if changes.is_empty() {
+            return;
+        }
+
+        /* the tileset holds the blockset, the map only records its changes */
+        let mut changed_blocks = Vec::new();
+        for change in &changes {
+            if let MapChange::Blockset(ref blockset_changes) = *change {
+                self.tileset
+                    .borrow_mut()
+                    .edit_blockset(|blockset| blockset.apply_changes(blockset_changes));
+                changed_blocks.extend(blockset_changes.iter().map(|&(id, _)| id));
+            }
+        }
+
+        let resized = changes.iter().any(|change| match *change {
+            MapChange::Resized { .. } => true,
+            _ => false,
+        });
+        if resized {
+            self.hovered = None;
+            self.rebuild();
+            return;
+        }
+
+        let width = Self::block_to_pixel_size(self.map.width());
+        let height = Self::block_to_pixel_size(self.map.height());
+
+        let pix_cache = Self::new_pixbuf_static(width, height, |context: &Context| {
+            context.set_source_pixbuf(&self.pix_cache, 0., 0.);
+            context.paint();
+
+            let tileset = self.tileset.borrow();
+            for change in &changes {
+                match *change {
+                    MapChange::Block { index, block } => {
+                        let (x, y) = self.coords(index);
+                        tileset.get_tile_pix(block).map(|tile| {
+                            context.set_source_pixbuf(&tile, x as f64, y as f64);
+                            context.paint();
+                        });
+                    }
+                    MapChange::Resized { .. } | MapChange::Blockset(_) => (),
+                }
+            }
+        });
+        self.pix_cache = pix_cache;
+        if !changed_blocks.is_empty() {
+            self.redraw_blocks(&changed_blocks);
+        }
+
+        for change in changes {
+            match change {
+                MapChange::Block { index, .. } => {
+                    let (x, y) = self.coords(index);
+                    let (x, y, width, height) =
+                        self.zoomed_area(x, y, BLOCK_SIZE as i32, BLOCK_SIZE as i32);
+                    self.widget.queue_draw_area(x, y, width, height);
+                }
+                MapChange::Resized { .. } | MapChange::Blockset(_) => (),
+            }
+        }
     }
 
     fn static_coords(index: usize, width: i32, _: i32) -> (i32, i32) {
@@ -159,8 +573,8 @@ impl Maparea {
     }
 
     pub fn coords(&self, index: usize) -> (i32, i32) {
-        let width = Self::block_to_pixel_size(self.width);
-        let height = Self::block_to_pixel_size(self.height);
+        let width = Self::block_to_pixel_size(self.map.width());
+        let height = Self::block_to_pixel_size(self.map.height());
         Self::static_coords(index, width, height)
     }
 
@@ -175,10 +589,10 @@ impl Maparea {
         {
             let b = *b_;
             let (x, y) = Self::static_coords(i, width, height);
-            let tile = tileset.get_tile_pix(b);
-
-            context.set_source_pixbuf(&tile, x as f64, y as f64);
-            context.paint();
+            tileset.get_tile_pix(b).map(|tile| {
+                context.set_source_pixbuf(&tile, x as f64, y as f64);
+                context.paint();
+            });
         })
     }
 
@@ -204,141 +618,222 @@ impl Maparea {
         Pixbuf::new_from_vec(data, 0, false, 8, width, height, width * 3)
     }
 
-    pub fn update_map_block(&mut self, map_index: usize, block_index: u8) {
-        self.mapset[map_index] = block_index;
-        let width = Self::block_to_pixel_size(self.width);
-        let height = Self::block_to_pixel_size(self.height);
+    fn paint(&self, context: &cairo::Context) {
+        /* everything below is drawn in unzoomed map pixels */
+        context.scale(self.scale, self.scale);
+        set_source_pixbuf_sharp(context, &self.pix_cache, 0., 0.);
+        context.paint();
 
-        let pix_cache = Self::new_pixbuf_static(width, height, |context: &Context| {
-            context.set_source_pixbuf(&self.pix_cache, 0., 0.);
-            context.paint();
+        if let Some(rect) = self.selection {
+            self.paint_rect_with_palette(context, rect, SELECT_PALETTE);
+        }
 
-            let (x, y) = self.coords(map_index);
-            context.set_source_pixbuf(
-                &self.tileset.borrow().get_tile_pix(block_index),
-                x as f64,
-                y as f64,
-            );
-            context.paint();
-        });
+        let hovered = self.hovered.map(|index| self.map.position(index));
+        match self.floating {
+            Some(ref region) => if let Some((x, y)) = hovered {
+                self.paint_floating(context, x, y, region);
+            },
+            None => {
+                let preview = self.active_tool().preview(&self.tileset.borrow(), hovered);
+                self.paint_preview(context, &preview.blocks);
+                if let Some(rect) = preview.highlight {
+                    self.paint_rect_with_palette(context, rect, HOVER_PALETTE);
+                }
+            }
+        }
 
-        self.pix_cache = pix_cache;
+        self.paint_overlays(context);
     }
 
-    fn paint(&self, context: &cairo::Context) {
-        let (x0, y0, x1, y1) = context.clip_extents();
-        let width = max(0, min(self.pix_cache.get_width(), x1 as i32) - x0 as i32);
-        let height = max(0, min(self.pix_cache.get_height(), y1 as i32) - y0 as i32);
-
-        let subpix = &self.pix_cache.new_subpixbuf(
-            x0 as i32,
-            y0 as i32,
-            width,
-            height,
-        );
-        context.set_source_pixbuf(subpix, x0 as f64, y0 as f64);
-        context.paint();
+    fn paint_overlays(&self, context: &cairo::Context) {
+        let width = Self::block_to_pixel_size(self.map.width()) as f64;
+        let height = Self::block_to_pixel_size(self.map.height()) as f64;
+        /* one screen pixel wide, and centered on screen pixels */
+        let pixel = 1. / self.scale;
+        let offset = pixel / 2.;
+        let set_color = |(red, green, blue): RgbTriple, alpha: f64| {
+            context.set_source_rgba(red as f64 / 255., green as f64 / 255., blue as f64 / 255., alpha)
+        };
+        context.set_line_width(pixel);
+
+        let grids = [
+            (self.overlays.tile_grid, TILE_SIZE, self.overlay_colors.tile_grid),
+            (self.overlays.block_grid, BLOCK_SIZE, self.overlay_colors.block_grid),
+        ];
+        for &(shown, step, color) in grids.iter() {
+            if !shown {
+                continue;
+            }
+            for x in (step..width as usize).step_by(step) {
+                context.move_to(x as f64 + offset, 0.);
+                context.line_to(x as f64 + offset, height);
+            }
+            for y in (step..height as usize).step_by(step) {
+                context.move_to(0., y as f64 + offset);
+                context.line_to(width, y as f64 + offset);
+            }
+            set_color(color, 0.5);
+            context.stroke();
+        }
 
-        if let Some(index) = self.hovered {
-            self.paint_tile_with_palette(context, index, HOVER_PALETTE);
+        let (player_x, player_y) = (PLAYER_ON_SCREEN.0 as f64, PLAYER_ON_SCREEN.1 as f64);
+        if self.overlays.camera {
+            let (camera_width, camera_height) = (
+                width - (SCREEN_WIDTH - STEP_SIZE) as f64,
+                height - (SCREEN_HEIGHT - STEP_SIZE) as f64,
+            );
+            if camera_width > 0. && camera_height > 0. {
+                context.rectangle(player_x + offset, player_y + offset, camera_width, camera_height);
+                set_color(self.overlay_colors.camera, 0.15);
+                context.fill_preserve();
+                set_color(self.overlay_colors.camera, 0.9);
+                context.set_dash(&[4. * pixel, 4. * pixel], 0.);
+                context.stroke();
+                context.set_dash(&[], 0.);
+            }
+        }
+
+        if let (true, Some((x, y))) = (self.overlays.screen, self.cursor) {
+            /* the player walks in steps, so the screen does too */
+            let step = STEP_SIZE as f64;
+            let (x, y) = ((x / step).floor() * step, (y / step).floor() * step);
+            context.rectangle(
+                x - player_x + offset,
+                y - player_y + offset,
+                SCREEN_WIDTH as f64,
+                SCREEN_HEIGHT as f64,
+            );
+            set_color(self.overlay_colors.screen, 0.9);
+            context.set_line_width(2. * pixel);
+            context.stroke();
         }
     }
 
-    fn paint_tile_with_palette(&self, context: &cairo::Context, index: usize, palette: RgbPalette) {
-        let (x0, y0, x1, y1) = context.clip_extents();
+    /// Preview of a pending paste, with its top left corner at `(x, y)`
+    fn paint_floating(&self, context: &cairo::Context, x: u8, y: u8, region: &Region) {
+        let mut blocks = Vec::new();
+        for ry in 0..region.height {
+            for rx in 0..region.width {
+                blocks.push((x as i32 + rx as i32, y as i32 + ry as i32, region.get(rx, ry)));
+            }
+        }
+        self.paint_preview(context, &blocks);
+    }
 
-        let (sel_x, sel_y) = self.coords(index);
-        let sel_x1 = sel_x + BLOCK_SIZE as i32;
-        let sel_y1 = sel_y + BLOCK_SIZE as i32;
+    /// Draw `(x, y, block)` cells over the map without changing it
+    fn paint_preview(&self, context: &cairo::Context, blocks: &[(i32, i32, u8)]) {
+        let tileset = self.tileset.borrow();
+        for &(x, y, block) in blocks {
+            if x < 0 || y < 0 || x >= self.map.width() as i32 || y >= self.map.height() as i32 {
+                continue;
+            }
 
-        let within_left = sel_x >= x0 as i32 && sel_x <= x1 as i32;
-        let within_right = sel_x1 >= x0 as i32 && sel_x1 <= x1 as i32;
-        let within_top = sel_y >= y0 as i32 && sel_y <= y1 as i32;
-        let within_bottom = sel_y >= y0 as i32 && sel_y <= y1 as i32;
+            tileset.get_tile_pix(block).map(|tile| {
+                set_source_pixbuf_sharp(
+                    context,
+                    &tile,
+                    (x * BLOCK_SIZE as i32) as f64,
+                    (y * BLOCK_SIZE as i32) as f64,
+                );
+                context.paint_with_alpha(0.7);
+            });
+        }
+    }
 
-        if (within_left || within_right) && (within_top || within_bottom) {
-            let x = max(sel_x, x0 as i32);
-            let y = max(sel_y, y0 as i32);
+    /* blocks are redrawn from their tiles, since CGB colors can't be swapped back from pixels */
+    fn paint_rect_with_palette(&self, context: &cairo::Context, rect: Rect, palette: RgbPalette) {
+        let (x0, y0, x1, y1) = context.clip_extents();
+        let block = BLOCK_SIZE as f64;
+        let tileset = self.tileset.borrow();
 
-            let width = min(sel_x1, x1 as i32) - x;
-            let height = min(sel_y1, y1 as i32) - y;
+        for (x, y) in rect.positions() {
+            let (left, top) = (x as f64 * block, y as f64 * block);
+            if left + block < x0 || top + block < y0 || left > x1 || top > y1 {
+                continue;
+            }
 
-            if width > 0 && height > 0 {
-                let subpix = self.pix_cache.new_subpixbuf(
-                    x as i32,
-                    y as i32,
-                    width as i32,
-                    height as i32,
-                );
-                let selected_subpix = change_palette(&subpix, self.palette, palette);
-                context.set_source_pixbuf(&selected_subpix, x as f64, y as f64);
+            let pix = self.map
+                .get(x, y)
+                .and_then(|id| tileset.get_tile_pix_with_palette(id, palette));
+            if let Some(pix) = pix {
+                set_source_pixbuf_sharp(context, &pix, left, top);
                 context.paint();
             }
         }
     }
 
-    pub fn hover_tile_at(&mut self, index: usize) {
-        self.hovered = Some(index);
+    /// Map cell under a widget position, clamped to the map
+    fn clamped_block_at(&self, pos: (f64, f64)) -> (u8, u8) {
+        let (x, y) = self.get_event_pos(pos);
+        (
+            min(x, self.map.width().saturating_sub(1)),
+            min(y, self.map.height().saturating_sub(1)),
+        )
     }
 
-    pub fn motion_notify(&mut self, el: &DrawingArea, ev: &gdk::EventMotion) {
-        let (pos_x, pos_y) = ev.get_position();
-        let block_x = pos_x as usize / BLOCK_SIZE;
-        let block_y = pos_y as usize / BLOCK_SIZE;
-
-        if block_x >= self.width as usize || block_y >= self.height as usize {
-            return;
+    /// Map cell under a widget position, if it is on the map
+    fn block_at(&self, pos: (f64, f64)) -> Option<(u8, u8)> {
+        let (pos_x, pos_y) = pos;
+        let (x, y) = self.get_event_pos(pos);
+        if pos_x < 0. || pos_y < 0. || x >= self.map.width() || y >= self.map.height() {
+            None
+        } else {
+            Some((x, y))
         }
+    }
 
-        let new_hovered = block_x + block_y * self.width as usize;
+    pub fn motion_notify(&mut self, _: &DrawingArea, ev: &gdk::EventMotion) {
+        let pos = self.clamped_block_at(ev.get_position());
+        match self.pressed {
+            Some(button) => self.with_tool(button == 3, |tool, ctx| tool.drag(ctx, pos)),
+            None => {
+                let map = &self.map;
+                self.tool.hover(map, pos);
+                self.picker.hover(map, pos);
+            }
+        }
 
-        if let Some(old_hovered) = self.hovered {
-            if new_hovered != old_hovered {
-                let (x, y) = self.coords(old_hovered);
-                el.queue_draw_area(x as i32, y as i32, BLOCK_SIZE as i32, BLOCK_SIZE as i32);
+        let new_hovered = self.block_at(ev.get_position())
+            .and_then(|(x, y)| self.map.index(x, y));
+        if new_hovered.is_some() && new_hovered != self.hovered {
+            self.hovered = new_hovered;
+            self.widget.queue_draw();
+        }
 
-                self.hover_tile_at(new_hovered);
-                let (x, y) = self.coords(new_hovered);
-                el.queue_draw_area(x as i32, y as i32, BLOCK_SIZE as i32, BLOCK_SIZE as i32);
-            }
-        } else {
-            self.hover_tile_at(new_hovered);
-            let (x, y) = self.coords(new_hovered);
-            el.queue_draw_area(x as i32, y as i32, BLOCK_SIZE as i32, BLOCK_SIZE as i32);
+        let (x, y) = ev.get_position();
+        self.cursor = Some((x / self.scale, y / self.scale));
+        if self.overlays.screen {
+            self.widget.queue_draw();
         }
     }
 
-    pub fn button_press(&mut self, el: &DrawingArea, ev: &gdk::EventButton) {
-        let (pos_x, pos_y) = ev.get_position();
-        let block_x = pos_x as usize / BLOCK_SIZE;
-        let block_y = pos_y as usize / BLOCK_SIZE;
-
-        if block_x >= self.width as usize || block_y >= self.height as usize {
+    pub fn button_press(&mut self, _: &DrawingArea, ev: &gdk::EventButton) {
+        let pos = match self.block_at(ev.get_position()) {
+            Some(pos) => pos,
+            None => return,
+        };
+        let button = ev.as_ref().button;
+
+        if self.floating.is_some() {
+            match button {
+                1 => self.finish_paste(pos),
+                3 => self.cancel_paste(),
+                _ => (),
+            }
             return;
         }
 
-        let block_index = block_x + block_y * self.width as usize;
-        assert!(block_index < self.mapset.len());
-
-        match ev.as_ref().button {
-            1 => self.button_press_left(el, block_index),
-            3 => self.button_press_right(el, block_index),
-            _ => (),
+        if self.pressed.is_none() && (button == 1 || button == 3) {
+            self.pressed = Some(button);
+            self.with_tool(button == 3, |tool, ctx| tool.press(ctx, pos));
         }
     }
 
-    pub fn button_press_left(&mut self, el: &DrawingArea, block_index: usize) {
-        let selected_block = self.tileset.borrow().selected;
-        if let Some(selected_block_index) = selected_block {
-            self.update_map_block(block_index, selected_block_index);
-            self.history.update(self.mapset.clone());
-            let (x, y) = self.coords(block_index);
-            el.queue_draw_area(x as i32, y as i32, BLOCK_SIZE as i32, BLOCK_SIZE as i32);
+    pub fn button_release(&mut self, _: &DrawingArea, ev: &gdk::EventButton) {
+        let button = ev.as_ref().button;
+        if self.pressed == Some(button) {
+            self.pressed = None;
+            self.with_tool(button == 3, |tool, ctx| tool.release(ctx));
         }
     }
-
-    pub fn button_press_right(&mut self, _: &DrawingArea, block_index: usize) {
-        let tile_index = self.mapset[block_index];
-        self.tileset.borrow_mut().select_tile_at(tile_index);
-    }
 }
@@ -1,6 +1,11 @@
 use gdk_pixbuf::Pixbuf;
 
+use std::cmp::max;
+
+use blockset::Block;
+use cgb::TileAttributes;
 use constants::*;
+use gfx::{GfxError, Tile, TileSheet, TILE_PIXELS};
 
 pub fn change_palette(tile: &Pixbuf, from_pal: RgbPalette, to_pal: RgbPalette) -> Pixbuf {
     let mut pxs: Vec<u8> = Vec::new();
@@ -36,3 +41,107 @@ pub fn change_palette(tile: &Pixbuf, from_pal: RgbPalette, to_pal: RgbPalette) -
         tile.get_rowstride(),
     )
 }
+
+/// Render a tile sheet image
+pub fn pixbuf_from_tiles(sheet: &TileSheet, palette: RgbPalette) -> Pixbuf {
+    let columns = max(1, sheet.columns);
+    let width = columns * TILE_SIZE;
+    let height = sheet.rows() * TILE_SIZE;
+
+    let mut pxs: Vec<u8> = Vec::with_capacity(width * height * 3);
+    for py in 0..height {
+        for px in 0..width {
+            let index = px / TILE_SIZE + (py / TILE_SIZE) * columns;
+            let shade = sheet
+                .tiles
+                .get(index)
+                .map_or(0, |tile| tile[px % TILE_SIZE + (py % TILE_SIZE) * TILE_SIZE]);
+            let (red, green, blue) = palette.shade(shade);
+
+            pxs.push(red);
+            pxs.push(green);
+            pxs.push(blue);
+        }
+    }
+
+    Pixbuf::new_from_vec(
+        pxs,
+        0,
+        false,
+        8,
+        width as i32,
+        height as i32,
+        width as i32 * 3,
+    )
+}
+
+/// Render a block, each tile read from its VRAM bank, flipped and colored
+/// as its attributes say
+pub fn pixbuf_from_block<F>(sheet: &TileSheet, block: &Block, palette_of: F) -> Pixbuf
+where
+    F: Fn(TileAttributes) -> RgbPalette,
+{
+    let mut pxs: Vec<u8> = Vec::with_capacity(BLOCK_SIZE * BLOCK_SIZE * 3);
+    for py in 0..BLOCK_SIZE {
+        for px in 0..BLOCK_SIZE {
+            let (slot_x, slot_y) = (px / TILE_SIZE, py / TILE_SIZE);
+            let attributes = block.attributes(slot_x, slot_y);
+            let tile = sheet.tile(attributes.bank, block.tile(slot_x, slot_y));
+
+            let (mut x, mut y) = (px % TILE_SIZE, py % TILE_SIZE);
+            if attributes.x_flip {
+                x = TILE_SIZE - 1 - x;
+            }
+            if attributes.y_flip {
+                y = TILE_SIZE - 1 - y;
+            }
+            let shade = tile.map_or(0, |tile| tile[x + y * TILE_SIZE]);
+            let (red, green, blue) = palette_of(attributes).shade(shade);
+
+            pxs.push(red);
+            pxs.push(green);
+            pxs.push(blue);
+        }
+    }
+
+    Pixbuf::new_from_vec(
+        pxs,
+        0,
+        false,
+        8,
+        BLOCK_SIZE as i32,
+        BLOCK_SIZE as i32,
+        BLOCK_SIZE as i32 * 3,
+    )
+}
+
+/// Split a tile sheet into tiles, mapping every pixel to its shade in `palette`
+pub fn tiles_from_pixbuf(pix: &Pixbuf, palette: RgbPalette) -> Result<Vec<Tile>, GfxError> {
+    let tiles_in_row = pix.get_width() as usize / TILE_SIZE;
+    let rows = pix.get_height() as usize / TILE_SIZE;
+    let rowstride = pix.get_rowstride() as usize;
+    let channels = pix.get_n_channels() as usize;
+
+    let pixels = unsafe { pix.get_pixels() };
+    let mut tiles = Vec::with_capacity(tiles_in_row * rows);
+    for ty in 0..rows {
+        for tx in 0..tiles_in_row {
+            let mut tile = [0; TILE_PIXELS];
+            for y in 0..TILE_SIZE {
+                for x in 0..TILE_SIZE {
+                    let (px, py) = (tx * TILE_SIZE + x, ty * TILE_SIZE + y);
+                    let offset = py * rowstride + px * channels;
+                    let color = rgb_triple_from(&pixels[offset..offset + 3]);
+                    tile[x + y * TILE_SIZE] = palette.shade_of(color).ok_or(GfxError::UnknownColor {
+                        x: px,
+                        y: py,
+                        color: color,
+                    })?;
+                }
+            }
+            tiles.push(tile);
+        }
+    }
+
+    Ok(tiles)
+}
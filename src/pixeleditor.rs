@@ -0,0 +1,295 @@
+use gtk::prelude::*;
+use gtk::{DrawingArea, Label};
+use cairo;
+use gdk;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use constants::*;
+use gfx::{self, Tile};
+use maparea::Maparea;
+use tileset::Tileset;
+
+/// On screen size of a tile pixel
+const PIXEL_SCALE: usize = 16;
+/// On screen size of a shade swatch
+const SWATCH_SIZE: usize = 32;
+
+/// What the left button does on the tile
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PixelTool {
+    Pencil,
+    Fill,
+}
+
+/// Side panel editing the pixels of one tile of the tile sheet
+#[derive(Debug)]
+pub struct PixelEditor {
+    tileset: Rc<RefCell<Tileset>>,
+    maparea: Rc<RefCell<Option<Maparea>>>,
+    /// Id of the tile being edited
+    tile: u8,
+    shade: u8,
+    tool: PixelTool,
+    /// Tiles as they were before each edit, latest last
+    undo_stack: Vec<(u8, Tile)>,
+    /// Tile as it was when the current pencil stroke started
+    stroke: Option<Tile>,
+    widget: DrawingArea,
+    shades_widget: DrawingArea,
+    label: Label,
+}
+
+impl PixelEditor {
+    pub fn from_data(
+        widget: DrawingArea,
+        shades_widget: DrawingArea,
+        label: Label,
+        tileset: Rc<RefCell<Tileset>>,
+        maparea: Rc<RefCell<Option<Maparea>>>,
+    ) -> Rc<RefCell<Self>> {
+        widget.add_events(drawing_area_mask_bits!());
+        shades_widget.add_events(drawing_area_mask_bits!());
+
+        let size = (TILE_SIZE * PIXEL_SCALE) as i32;
+        widget.set_size_request(size, size);
+        shades_widget.set_size_request((SWATCH_SIZE * 4) as i32, SWATCH_SIZE as i32);
+
+        let editor = PixelEditor {
+            tileset: tileset,
+            maparea: maparea,
+            tile: 0,
+            shade: 3,
+            tool: PixelTool::Pencil,
+            undo_stack: Vec::new(),
+            stroke: None,
+            widget: widget,
+            shades_widget: shades_widget,
+            label: label,
+        };
+        editor.refresh();
+        let cell = Rc::new(RefCell::new(editor));
+
+        {
+            let editor = cell.borrow();
+
+            editor.widget.connect_draw(clone!(cell => move |_, context| {
+                cell.borrow().paint(&context);
+                Inhibit::default()
+            }));
+
+            editor.widget.connect_button_press_event(clone!(cell => move |_, ev| {
+                cell.borrow_mut().button_press(ev);
+                Inhibit::default()
+            }));
+
+            editor.widget.connect_motion_notify_event(clone!(cell => move |_, ev| {
+                cell.borrow_mut().motion_notify(ev);
+                Inhibit::default()
+            }));
+
+            editor.widget.connect_button_release_event(clone!(cell => move |_, _| {
+                cell.borrow_mut().finish_stroke();
+                Inhibit::default()
+            }));
+
+            editor.shades_widget.connect_draw(clone!(cell => move |_, context| {
+                cell.borrow().paint_shades(&context);
+                Inhibit::default()
+            }));
+
+            editor.shades_widget.connect_button_press_event(clone!(cell => move |_, ev| {
+                let (x, _) = ev.get_position();
+                cell.borrow_mut().set_shade((x as usize / SWATCH_SIZE) as u8);
+                Inhibit::default()
+            }));
+        }
+
+        cell
+    }
+
+    /// Edit another tile of the sheet
+    pub fn set_tile_id(&mut self, id: u8) {
+        self.finish_stroke();
+        self.tile = id;
+        self.refresh();
+    }
+
+    pub fn set_tool(&mut self, tool: PixelTool) {
+        self.tool = tool;
+    }
+
+    fn set_shade(&mut self, shade: u8) {
+        if shade < 4 {
+            self.shade = shade;
+            self.shades_widget.queue_draw();
+        }
+    }
+
+    /// Forget the edits, e.g. after another tile sheet was opened
+    pub fn reset(&mut self) {
+        self.undo_stack.clear();
+        self.set_tile_id(0);
+    }
+
+    fn refresh(&self) {
+        self.label.set_label(&format!("Tile {:#04x}", self.tile));
+        self.widget.queue_draw();
+        self.shades_widget.queue_draw();
+    }
+
+    fn current(&self) -> Option<Tile> {
+        self.tileset.borrow().tiles().get(self.tile as usize).cloned()
+    }
+
+    /// Replace a tile everywhere it shows: the sheet, the blocks and the map
+    fn apply(&mut self, id: u8, tile: Tile) {
+        let blocks = self.tileset.borrow_mut().set_tile(id, tile);
+        self.maparea
+            .borrow_mut()
+            .as_mut()
+            .map(|maparea| maparea.redraw_blocks(&blocks));
+        self.widget.queue_draw();
+    }
+
+    /// Apply `f` to the tile as one undo step
+    pub fn transform<F: FnOnce(&Tile) -> Tile>(&mut self, f: F) {
+        if let Some(tile) = self.current() {
+            let changed = f(&tile);
+            if changed[..] != tile[..] {
+                self.undo_stack.push((self.tile, tile));
+                let id = self.tile;
+                self.apply(id, changed);
+            }
+        }
+    }
+
+    /* the whole stroke is a single undo step */
+    fn finish_stroke(&mut self) {
+        if let (Some(before), Some(after)) = (self.stroke.take(), self.current()) {
+            if before[..] != after[..] {
+                self.undo_stack.push((self.tile, before));
+            }
+        }
+    }
+
+    pub fn undo(&mut self) {
+        if let Some((id, tile)) = self.undo_stack.pop() {
+            self.set_tile_id(id);
+            self.apply(id, tile);
+        }
+    }
+
+    fn pixel_at(&self, pos: (f64, f64)) -> Option<(usize, usize)> {
+        let (x, y) = pos;
+        let (x, y) = (x as usize / PIXEL_SCALE, y as usize / PIXEL_SCALE);
+        if pos.0 >= 0. && pos.1 >= 0. && x < TILE_SIZE && y < TILE_SIZE {
+            Some((x, y))
+        } else {
+            None
+        }
+    }
+
+    fn button_press(&mut self, ev: &gdk::EventButton) {
+        let (x, y) = match self.pixel_at(ev.get_position()) {
+            Some(pixel) => pixel,
+            None => return,
+        };
+        let shade = self.shade;
+
+        match (ev.as_ref().button, self.tool) {
+            (1, PixelTool::Pencil) => {
+                self.stroke = self.current();
+                self.paint_pixel(x, y);
+            }
+            (1, PixelTool::Fill) => self.transform(|tile| {
+                let mut tile = *tile;
+                gfx::fill(&mut tile, x, y, shade);
+                tile
+            }),
+            (3, _) => {
+                let picked = self.current().map(|tile| tile[x + y * TILE_SIZE]);
+                picked.map(|shade| self.set_shade(shade));
+            }
+            _ => (),
+        }
+    }
+
+    fn motion_notify(&mut self, ev: &gdk::EventMotion) {
+        if self.stroke.is_some() {
+            self.pixel_at(ev.get_position()).map(|(x, y)| self.paint_pixel(x, y));
+        }
+    }
+
+    fn paint_pixel(&mut self, x: usize, y: usize) {
+        if let Some(mut tile) = self.current() {
+            if tile[x + y * TILE_SIZE] != self.shade {
+                tile[x + y * TILE_SIZE] = self.shade;
+                let id = self.tile;
+                self.apply(id, tile);
+            }
+        }
+    }
+
+    fn paint(&self, context: &cairo::Context) {
+        let tile = match self.current() {
+            Some(tile) => tile,
+            None => return,
+        };
+        let palette = self.tileset.borrow().palette();
+
+        for y in 0..TILE_SIZE {
+            for x in 0..TILE_SIZE {
+                let (red, green, blue) = palette.shade(tile[x + y * TILE_SIZE]);
+                context.set_source_rgb(red as f64 / 255., green as f64 / 255., blue as f64 / 255.);
+                context.rectangle(
+                    (x * PIXEL_SCALE) as f64,
+                    (y * PIXEL_SCALE) as f64,
+                    PIXEL_SCALE as f64,
+                    PIXEL_SCALE as f64,
+                );
+                context.fill();
+            }
+        }
+
+        /* pixel grid */
+        let size = (TILE_SIZE * PIXEL_SCALE) as f64;
+        context.set_source_rgba(0.5, 0.5, 0.5, 0.5);
+        context.set_line_width(1.);
+        for i in 1..TILE_SIZE {
+            let offset = (i * PIXEL_SCALE) as f64 + 0.5;
+            context.move_to(offset, 0.);
+            context.line_to(offset, size);
+            context.move_to(0., offset);
+            context.line_to(size, offset);
+        }
+        context.stroke();
+    }
+
+    fn paint_shades(&self, context: &cairo::Context) {
+        let palette = self.tileset.borrow().palette();
+        for shade in 0..4 {
+            let (red, green, blue) = palette.shade(shade);
+            context.set_source_rgb(red as f64 / 255., green as f64 / 255., blue as f64 / 255.);
+            context.rectangle(
+                (shade as usize * SWATCH_SIZE) as f64,
+                0.,
+                SWATCH_SIZE as f64,
+                SWATCH_SIZE as f64,
+            );
+            context.fill();
+        }
+
+        /* frame around the current shade */
+        context.set_source_rgb(1., 0., 0.);
+        context.set_line_width(3.);
+        context.rectangle(
+            (self.shade as usize * SWATCH_SIZE) as f64 + 1.5,
+            1.5,
+            SWATCH_SIZE as f64 - 3.,
+            SWATCH_SIZE as f64 - 3.,
+        );
+        context.stroke();
+    }
+}
@@ -1,12 +1,99 @@
+use gtk::prelude::*;
+use gtk::{DrawingArea, Viewport};
+use gdk;
+use gdk::prelude::*;
+use gdk::ScrollDirection;
+use gdk_pixbuf::Pixbuf;
+use cairo;
+
 use constants::*;
 
+/// Zoom levels, 1 showing a block as `BLOCK_SIZE` screen pixels
+pub const ZOOM_LEVELS: [f64; 10] = [0.25, 0.5, 0.75, 1., 1.5, 2., 3., 4., 6., 8.];
+
 pub trait Positionable {
+    /// Zoom the widget is drawn at
+    fn scale(&self) -> f64 {
+        1.
+    }
+
     fn get_event_pos(&self, pos: (f64, f64)) -> (u8, u8) {
-        get_event_pos(pos)
+        get_event_pos(pos, self.scale())
+    }
+
+    /// Widget area showing `width` x `height` unzoomed pixels at `(x, y)`
+    fn zoomed_area(&self, x: i32, y: i32, width: i32, height: i32) -> (i32, i32, i32, i32) {
+        let scale = self.scale();
+        (
+            (x as f64 * scale).floor() as i32,
+            (y as f64 * scale).floor() as i32,
+            (width as f64 * scale).ceil() as i32 + 1,
+            (height as f64 * scale).ceil() as i32 + 1,
+        )
     }
 }
 
-pub fn get_event_pos(pos: (f64, f64)) -> (u8, u8) {
+pub fn get_event_pos(pos: (f64, f64), scale: f64) -> (u8, u8) {
     let (x, y) = pos;
-    ((x / BLOCK_SIZE as f64) as u8, (y / BLOCK_SIZE as f64) as u8)
+    let size = BLOCK_SIZE as f64 * scale;
+    ((x / size) as u8, (y / size) as u8)
+}
+
+/// The zoom level after `scale`, going in or out
+pub fn next_zoom(scale: f64, zoom_in: bool) -> f64 {
+    let (first, last) = (ZOOM_LEVELS[0], ZOOM_LEVELS[ZOOM_LEVELS.len() - 1]);
+    if zoom_in {
+        ZOOM_LEVELS.iter().cloned().find(|&level| level > scale + 1e-6).unwrap_or(last)
+    } else {
+        ZOOM_LEVELS.iter().rev().cloned().find(|&level| level < scale - 1e-6).unwrap_or(first)
+    }
+}
+
+/// New zoom level for a Ctrl+scroll, or `None` if the scroll should just scroll
+pub fn scroll_zoom(scale: f64, ev: &gdk::EventScroll) -> Option<f64> {
+    if !ev.get_state().contains(gdk::ModifierType::CONTROL_MASK) {
+        return None;
+    }
+
+    match ev.get_direction() {
+        ScrollDirection::Up => Some(next_zoom(scale, true)),
+        ScrollDirection::Down => Some(next_zoom(scale, false)),
+        ScrollDirection::Smooth => {
+            let (_, dy) = ev.get_delta();
+            if dy < 0. {
+                Some(next_zoom(scale, true))
+            } else if dy > 0. {
+                Some(next_zoom(scale, false))
+            } else {
+                Some(scale)
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Scroll the viewport around `widget` so that the point at `pos` stays
+/// under the cursor once the widget grows by `factor`
+pub fn zoom_around(widget: &DrawingArea, pos: (f64, f64), factor: f64) {
+    let viewport = match widget.get_parent().and_then(|parent| parent.downcast::<Viewport>().ok()) {
+        Some(viewport) => viewport,
+        None => return,
+    };
+
+    let (x, y) = pos;
+    let axes = [(viewport.get_hadjustment(), x), (viewport.get_vadjustment(), y)];
+    for &(ref adjustment, pos) in axes.iter() {
+        if let Some(ref adjustment) = *adjustment {
+            let on_screen = pos - adjustment.get_value();
+            /* the new size is only allocated later, so make room for the value now */
+            adjustment.set_upper(adjustment.get_upper() * factor);
+            adjustment.set_value(pos * factor - on_screen);
+        }
+    }
+}
+
+/// Use `pix` as the source at `(x, y)`, keeping its pixels sharp when zoomed in
+pub fn set_source_pixbuf_sharp(context: &cairo::Context, pix: &Pixbuf, x: f64, y: f64) {
+    context.set_source_pixbuf(pix, x, y);
+    context.get_source().set_filter(cairo::Filter::Nearest);
 }
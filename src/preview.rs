@@ -0,0 +1,170 @@
+use gtk::prelude::*;
+use gtk::{DrawingArea, Window, WindowType};
+use cairo;
+use gdk::enums::key;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use constants::*;
+use maparea::Maparea;
+use position::set_source_pixbuf_sharp;
+
+/// On screen size of a Game Boy pixel
+const PREVIEW_SCALE: f64 = 2.;
+/// Sprites stand this many pixels above their step, as in the games
+const SPRITE_RISE: usize = 4;
+
+/// The player facing down: shades 0 to 3, dots being transparent
+const PLAYER_SPRITE: [&str; STEP_SIZE] = [
+    ".....333333.....",
+    "....32222223....",
+    "...3222222223...",
+    "...3333333333...",
+    "..330000000033..",
+    "..300300003003..",
+    "..300300003003..",
+    "...3000000003...",
+    "....33000033....",
+    "...3113333113...",
+    "..311133331113..",
+    "..300311113003..",
+    "...3331111333...",
+    "....31133113....",
+    "....3333.3333...",
+    ".....33...33....",
+];
+
+/// Window walking a player around the map, seen through the 160x144 screen
+#[derive(Debug)]
+pub struct WalkPreview {
+    maparea: Rc<RefCell<Option<Maparea>>>,
+    /// Step the player stands on, in `STEP_SIZE` units of the map
+    player: (usize, usize),
+    window: Window,
+    widget: DrawingArea,
+}
+
+impl WalkPreview {
+    /// Open the preview, the player standing on the hovered block or the top left one
+    pub fn open(parent: &Window, maparea: Rc<RefCell<Option<Maparea>>>) {
+        let player = maparea
+            .borrow()
+            .as_ref()
+            .and_then(|maparea| maparea.hovered_position())
+            .map_or((0, 0), |(x, y)| (x as usize * 2, y as usize * 2));
+
+        let window = Window::new(WindowType::Toplevel);
+        window.set_transient_for(Some(parent));
+        window.set_resizable(false);
+        let widget = DrawingArea::new();
+        widget.set_size_request(
+            (SCREEN_WIDTH as f64 * PREVIEW_SCALE) as i32,
+            (SCREEN_HEIGHT as f64 * PREVIEW_SCALE) as i32,
+        );
+        window.add(&widget);
+
+        let preview = WalkPreview {
+            maparea: maparea,
+            player: player,
+            window: window,
+            widget: widget,
+        };
+        preview.refresh();
+        let cell = Rc::new(RefCell::new(preview));
+
+        let preview = cell.borrow();
+
+        preview.widget.connect_draw(clone!(cell => move |_, context| {
+            cell.borrow().paint(&context);
+            Inhibit::default()
+        }));
+
+        /* one step per key press, like tapping the D-pad */
+        preview.window.connect_key_press_event(clone!(cell => move |window, ev| {
+            let step = match ev.get_keyval() {
+                key::Left => (-1, 0),
+                key::Right => (1, 0),
+                key::Up => (0, -1),
+                key::Down => (0, 1),
+                key::Escape => {
+                    window.destroy();
+                    return Inhibit(true);
+                }
+                _ => return Inhibit(false),
+            };
+            cell.borrow_mut().walk(step);
+            Inhibit(true)
+        }));
+
+        preview.window.show_all();
+    }
+
+    fn refresh(&self) {
+        let (x, y) = self.player;
+        self.window.set_title(&format!("Walk Preview - step ({}, {})", x, y));
+        self.widget.queue_draw();
+    }
+
+    /// Take one step, unless it leads off the map or into something solid
+    fn walk(&mut self, (dx, dy): (i32, i32)) {
+        let (x, y) = (self.player.0 as i32 + dx, self.player.1 as i32 + dy);
+        if x < 0 || y < 0 {
+            return;
+        }
+        let (x, y) = (x as usize, y as usize);
+
+        let walkable = match *self.maparea.borrow() {
+            Some(ref maparea) => {
+                let map = maparea.map();
+                let tileset = maparea.tileset();
+                let tileset = tileset.borrow();
+                let on_map = x < map.width() as usize * 2 && y < map.height() as usize * 2;
+                on_map && tileset.collision().map_or(true, |collision| {
+                    collision.is_walkable(map, tileset.blockset(), x, y)
+                })
+            }
+            None => false,
+        };
+        if walkable {
+            self.player = (x, y);
+            self.refresh();
+        }
+    }
+
+    fn paint(&self, context: &cairo::Context) {
+        let maparea = self.maparea.borrow();
+        let maparea = match *maparea {
+            Some(ref maparea) => maparea,
+            None => return,
+        };
+        let palette = maparea.tileset().borrow().palette();
+        let set_shade = |shade: u8| {
+            let (red, green, blue) = palette.shade(shade);
+            context.set_source_rgb(red as f64 / 255., green as f64 / 255., blue as f64 / 255.);
+        };
+        context.scale(PREVIEW_SCALE, PREVIEW_SCALE);
+
+        /* past the map edges, where the games would show the border block */
+        set_shade(3);
+        context.paint();
+
+        /* the screen keeps the player at the same spot, the map scrolls under it */
+        let (player_x, player_y) = (self.player.0 * STEP_SIZE, self.player.1 * STEP_SIZE);
+        let camera_x = player_x as f64 - PLAYER_ON_SCREEN.0 as f64;
+        let camera_y = player_y as f64 - PLAYER_ON_SCREEN.1 as f64;
+        set_source_pixbuf_sharp(context, maparea.pix(), -camera_x, -camera_y);
+        context.paint();
+
+        let (sprite_x, sprite_y) = (PLAYER_ON_SCREEN.0, PLAYER_ON_SCREEN.1 - SPRITE_RISE);
+        for (y, row) in PLAYER_SPRITE.iter().enumerate() {
+            for (x, pixel) in row.chars().enumerate() {
+                if let Some(shade) = pixel.to_digit(4) {
+                    set_shade(shade as u8);
+                    context.rectangle((sprite_x + x) as f64, (sprite_y + y) as f64, 1., 1.);
+                    context.fill();
+                }
+            }
+        }
+    }
+}
@@ -9,57 +9,233 @@ use std::cmp::{min, max};
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use blockset::{Block, Blockset};
+use cgb::{BgPalettes, TileAttributes};
+use collision::Collision;
 use constants::*;
-use palette::change_palette;
+use gfx::{Tile, TileSheet};
+use map::{Rect, Region};
+use palette::{pixbuf_from_block, pixbuf_from_tiles};
 use position::*;
 
+/// Blocks shown in each row of the panel
+const PANEL_COLUMNS: usize = 16;
 
 #[derive(Clone, Debug)]
 pub struct Tileset {
     width: i32,
     height: i32,
     pub selected: Option<u8>,
+    brush: Option<Region>,
+    brush_rect: Option<Rect>,
+    drag_start: Option<(u8, u8)>,
     hovered: Option<u8>,
     pix_cache: Pixbuf,
+    tiles: TileSheet,
+    /// `tiles` rendered with `palette`
+    sheet: Pixbuf,
     palette: RgbPalette,
+    /// CGB palettes the blocks are shown with instead of `palette`, if any
+    bg_palettes: Option<BgPalettes>,
+    collision: Option<Collision>,
+    scale: f64,
     widget: DrawingArea,
-    _max_block_id: u8,
+    blockset: Blockset,
+}
+
+impl Positionable for Tileset {
+    fn scale(&self) -> f64 {
+        self.scale
+    }
 }
 
 impl Tileset {
     pub fn new(
         width: i32,
         height: i32,
-        pix: &Pixbuf,
-        blockset: &[u8],
+        tiles: TileSheet,
+        blockset: Blockset,
         widget: DrawingArea,
     ) -> Self {
-        let tileset_pix_cache = Self::build_tileset_pix(width, height, pix, blockset);
+        let sheet = pixbuf_from_tiles(&tiles, BASE_PALETTE);
+        let tileset_pix_cache = Self::build_tileset_pix(width, height, &blockset, |block| {
+            pixbuf_from_block(&tiles, block, |_| BASE_PALETTE)
+        });
         Tileset {
             width: width,
             height: height,
             selected: None,
+            brush: None,
+            brush_rect: None,
+            drag_start: None,
             hovered: None,
             pix_cache: tileset_pix_cache,
+            tiles: tiles,
+            sheet: sheet,
             palette: BASE_PALETTE,
+            bg_palettes: None,
+            collision: None,
+            scale: 1.,
             widget: widget,
-            _max_block_id: (pix.get_width() / TILE_SIZE as i32) as u8 *
-                (pix.get_height() / TILE_SIZE as i32) as u8,
+            blockset: blockset,
+        }
+    }
+
+    pub fn palette(&self) -> RgbPalette {
+        self.palette
+    }
+
+    /// Show the tiles through another palette; the tiles themselves don't change
+    pub fn set_palette(&mut self, palette: RgbPalette) {
+        self.palette = palette;
+        self.sheet = pixbuf_from_tiles(&self.tiles, palette);
+        self.rebuild_pix();
+    }
+
+    pub fn bg_palettes(&self) -> Option<BgPalettes> {
+        self.bg_palettes
+    }
+
+    /// Show the blocks through CGB palettes, or through `palette` when `None`
+    pub fn set_bg_palettes(&mut self, bg_palettes: Option<BgPalettes>) {
+        self.bg_palettes = bg_palettes;
+        self.rebuild_pix();
+    }
+
+    /// Which steps of a map built from these blocks can be walked on, if known
+    pub fn collision(&self) -> Option<&Collision> {
+        self.collision.as_ref()
+    }
+
+    pub fn set_collision(&mut self, collision: Option<Collision>) {
+        self.collision = collision;
+    }
+
+    /// Palette a tile with `attributes` is shown with
+    fn tile_palette(&self, attributes: TileAttributes) -> RgbPalette {
+        match self.bg_palettes {
+            Some(ref palettes) => palettes.rgb_palette(attributes.palette),
+            None => self.palette,
+        }
+    }
+
+    fn render_block(&self, block: &Block) -> Pixbuf {
+        pixbuf_from_block(&self.tiles, block, |attributes| self.tile_palette(attributes))
+    }
+
+    fn rebuild_pix(&mut self) {
+        let pix = Self::build_tileset_pix(self.width, self.height, &self.blockset, |block| {
+            self.render_block(block)
+        });
+        self.pix_cache = pix;
+        self.widget.queue_draw();
+    }
+
+    pub fn tiles(&self) -> &[Tile] {
+        &self.tiles.tiles
+    }
+
+    /// Change the tile at sheet index `id`, redrawing the blocks made of it.
+    /// Returns their ids.
+    pub fn set_tile(&mut self, id: u8, tile: Tile) -> Vec<u8> {
+        match self.tiles.tiles.get_mut(id as usize) {
+            Some(old) => *old = tile,
+            None => return Vec::new(),
+        }
+
+        self.sheet = pixbuf_from_tiles(&self.tiles, self.palette);
+        self.rebuild_pix();
+        TileSheet::bank_and_id(id as usize)
+            .map_or(Vec::new(), |(bank, tile)| self.blockset.blocks_using(bank, tile))
+    }
+
+    /// Replace the tile sheet, blockset and CGB palettes, keeping the widget and its events
+    pub fn load(&mut self, tiles: TileSheet, blockset: Blockset, bg_palettes: Option<BgPalettes>) {
+        self.sheet = pixbuf_from_tiles(&tiles, self.palette);
+        self.tiles = tiles;
+        self.bg_palettes = bg_palettes;
+        self.selected = None;
+        self.set_blockset(blockset);
+    }
+
+    pub fn blockset(&self) -> &Blockset {
+        &self.blockset
+    }
+
+    /// Tile sheet image the blocks are made of
+    pub fn sheet(&self) -> &Pixbuf {
+        &self.sheet
+    }
+
+    /// Change the blocks and redraw them, keeping the selection when it still exists
+    pub fn edit_blockset<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut Blockset) -> R,
+    {
+        let mut blockset = self.blockset.clone();
+        let result = f(&mut blockset);
+        self.set_blockset(blockset);
+        result
+    }
+
+    fn set_blockset(&mut self, blockset: Blockset) {
+        let (width, height) = Self::panel_size(blockset.len());
+        self.width = width;
+        self.height = height;
+        self.blockset = blockset;
+        self.rebuild_pix();
+        self.brush = None;
+        self.brush_rect = None;
+        self.drag_start = None;
+        self.hovered = None;
+
+        self.update_size_request();
+        self.widget.queue_draw();
+        let selected = min(self.selected.unwrap_or(0), self.get_max_block_id());
+        self.selected = None;
+        self.select_tile_at(selected);
+    }
+
+    /// Zoom the panel, between the smallest and largest of `ZOOM_LEVELS`
+    pub fn set_scale(&mut self, scale: f64) {
+        let (first, last) = (ZOOM_LEVELS[0], ZOOM_LEVELS[ZOOM_LEVELS.len() - 1]);
+        self.scale = scale.max(first).min(last);
+        self.update_size_request();
+        self.widget.queue_draw();
+    }
+
+    fn update_size_request(&self) {
+        self.widget.set_size_request(
+            (self.width as f64 * self.scale).ceil() as i32,
+            (self.height as f64 * self.scale).ceil() as i32,
+        );
+    }
+
+    /// Ctrl+scroll zooms around the cursor; returns whether the scroll was used
+    pub fn scroll(&mut self, ev: &gdk::EventScroll) -> bool {
+        match scroll_zoom(self.scale, ev) {
+            Some(scale) => {
+                let old_scale = self.scale;
+                self.set_scale(scale);
+                zoom_around(&self.widget, ev.get_position(), self.scale / old_scale);
+                true
+            }
+            None => false,
         }
     }
 
     pub fn get_max_block_id(&self) -> u8 {
-        self._max_block_id
+        self.blockset.len().saturating_sub(1) as u8
     }
 
-    pub fn from_data(widget: DrawingArea, blockset: &Vec<u8>, pix: &Pixbuf) -> Rc<RefCell<Self>> {
+    pub fn from_data(widget: DrawingArea, blockset: Blockset, tiles: TileSheet) -> Rc<RefCell<Self>> {
         widget.add_events(drawing_area_mask_bits!());
+        widget.add_events(gdk::EventMask::SCROLL_MASK.bits() as i32);
 
-        let width = blockset.len() as i32 * (TILE_SIZE as i32 / 4) as i32;
-        let height = BLOCK_SIZE as i32;
+        let (width, height) = Self::panel_size(blockset.len());
         widget.set_size_request(width, height);
 
-        let tileset = Tileset::new(width, height, pix, blockset, widget);
+        let tileset = Tileset::new(width, height, tiles, blockset, widget);
         let cell = Rc::new(RefCell::new(tileset));
 
         {
@@ -79,36 +255,45 @@ impl Tileset {
                 Inhibit::default()
             }));
 
+            widget.connect_button_release_event(clone!(cell => move|el, ev| {
+                cell.borrow_mut().button_release(el, ev);
+                Inhibit::default()
+            }));
+
             widget.connect_draw(clone!(cell => move |_, context| {
                 cell.borrow_mut().paint(&context);
                 Inhibit::default()
             }));
+
+            widget.connect_scroll_event(clone!(cell => move |_, ev| {
+                Inhibit(cell.borrow_mut().scroll(ev))
+            }));
         }
 
         cell
     }
 
-    fn build_tileset_pix(width: i32, height: i32, pix: &Pixbuf, blockset: &[u8]) -> Pixbuf {
-        Self::new_pixbuf_static(width, height, |context| {
-            let tileset_width = pix.get_width() / TILE_SIZE as i32;
-            let tileset_height = pix.get_height() / TILE_SIZE as i32;
-            let max_block_id = tileset_width * tileset_height;
-            for (i, b_) in blockset.iter().enumerate() {
-                let mut b = *b_ as i32;
-                if b > max_block_id {
-                    continue;
-                }
-                let tile = pix.new_subpixbuf(
-                    TILE_SIZE as i32 * (b % tileset_width),
-                    TILE_SIZE as i32 * ((b / tileset_width) as i32),
-                    TILE_SIZE as i32,
-                    TILE_SIZE as i32,
-                );
+    /// Pixel size of a panel showing `len` blocks
+    fn panel_size(len: usize) -> (i32, i32) {
+        let columns = min(len, PANEL_COLUMNS);
+        let rows = (len + PANEL_COLUMNS - 1) / PANEL_COLUMNS;
+        (
+            (columns * BLOCK_SIZE) as i32,
+            (max(rows, 1) * BLOCK_SIZE) as i32,
+        )
+    }
 
+    fn build_tileset_pix<F>(width: i32, height: i32, blockset: &Blockset, render: F) -> Pixbuf
+    where
+        F: Fn(&Block) -> Pixbuf,
+    {
+        Self::new_pixbuf_static(width, height, |context| {
+            for (i, block) in blockset.iter().enumerate() {
+                let (block_x, block_y) = (i % PANEL_COLUMNS, i / PANEL_COLUMNS);
                 context.set_source_pixbuf(
-                    &tile,
-                    (((i % 4) * TILE_SIZE) + (i / 16) * BLOCK_SIZE) as f64,
-                    ((((i / 4) % 4) as i32) * TILE_SIZE as i32) as f64,
+                    &render(block),
+                    (block_x * BLOCK_SIZE) as f64,
+                    (block_y * BLOCK_SIZE) as f64,
                 );
                 context.paint();
             }
@@ -142,28 +327,71 @@ impl Tileset {
 
     pub fn coords(&self, index: u8) -> (i32, i32) {
         (
-            (index as i32 * BLOCK_SIZE as i32) % self.width as i32,
-            (index as i32 * BLOCK_SIZE as i32) / self.width as i32,
+            (index as usize % PANEL_COLUMNS * BLOCK_SIZE) as i32,
+            (index as usize / PANEL_COLUMNS * BLOCK_SIZE) as i32,
         )
     }
 
+    /// Block shown at a cell of the panel, if any
+    fn block_at(&self, (x, y): (u8, u8)) -> Option<u8> {
+        let index = x as usize + y as usize * PANEL_COLUMNS;
+        if (x as usize) < PANEL_COLUMNS && index < self.blockset.len() {
+            Some(index as u8)
+        } else {
+            None
+        }
+    }
+
+    /// Blocks stamped by the pencil: the multi-block brush if there is one,
+    /// else the selected block alone
+    pub fn brush(&self) -> Option<Region> {
+        self.brush.clone().or_else(|| {
+            self.selected.map(|block| Region::new(1, 1, vec![block]))
+        })
+    }
+
+    /// Use `region` as the brush, e.g. after picking it from the map
+    pub fn set_brush(&mut self, region: Region) {
+        if region.width == 0 || region.height == 0 {
+            return;
+        }
+
+        self.select_tile_at(region.get(0, 0));
+        if region.width > 1 || region.height > 1 {
+            self.brush = Some(region);
+        }
+    }
+
+    /// Turn the blocks in a rectangle of the panel into the brush
+    fn set_brush_rect(&mut self, rect: Rect) {
+        let blocks: Option<Vec<u8>> = rect.positions()
+            .into_iter()
+            .map(|pos| self.block_at(pos))
+            .collect();
+
+        if let Some(blocks) = blocks {
+            self.set_brush(Region::new(rect.width, rect.height, blocks));
+            self.brush_rect = Some(rect);
+            self.widget.queue_draw();
+        }
+    }
+
     pub fn select_tile_at(&mut self, index: u8) {
         let should_select = self.selected.map_or(true, |old| index != old);
+        if self.brush.take().is_some() | self.brush_rect.take().is_some() {
+            self.widget.queue_draw();
+        }
         if !should_select {
             return;
         }
 
-        let el = &self.widget;
-
         // clear highlighting from previously selected tile (if any)
         self.selected.map(|old_selected| {
-            let (x, y) = self.coords(old_selected);
-            el.queue_draw_area(x as i32, y as i32, BLOCK_SIZE as i32, BLOCK_SIZE as i32);
+            self.queue_draw_block(self.coords(old_selected));
         });
 
         // highlight newly selected tile
-        let (x, y) = self.coords(index);
-        el.queue_draw_area(x as i32, y as i32, BLOCK_SIZE as i32, BLOCK_SIZE as i32);
+        self.queue_draw_block(self.coords(index));
 
         self.selected = Some(index);
     }
@@ -172,103 +400,107 @@ impl Tileset {
         self.hovered = Some(index);
     }
 
-    pub fn get_tile_pix(&self, index: u8) -> Pixbuf {
+    /// Image of block `index`, or `None` if the blockset has no such block
+    pub fn get_tile_pix(&self, index: u8) -> Option<Pixbuf> {
+        if index as usize >= self.blockset.len() {
+            return None;
+        }
         let (x, y) = self.coords(index);
-        self.pix_cache.new_subpixbuf(
+        Some(self.pix_cache.new_subpixbuf(
             x as i32,
             y as i32,
             BLOCK_SIZE as i32,
             BLOCK_SIZE as i32,
-        )
+        ))
     }
 
     fn paint(&self, context: &cairo::Context) {
-        let (x0, y0, x1, y1) = context.clip_extents();
-        let width = max(0, min(self.pix_cache.get_width(), x1 as i32) - x0 as i32);
-        let height = max(0, min(self.pix_cache.get_height(), y1 as i32) - y0 as i32);
-
-        let subpix = &self.pix_cache.new_subpixbuf(
-            x0 as i32,
-            y0 as i32,
-            width,
-            height,
-        );
-        context.set_source_pixbuf(subpix, x0 as f64, y0 as f64);
+        context.scale(self.scale, self.scale);
+        set_source_pixbuf_sharp(context, &self.pix_cache, 0., 0.);
         context.paint();
 
         if let Some(index) = self.hovered {
             self.paint_tile_with_palette(context, index, HOVER_PALETTE);
         }
 
-        if let Some(index) = self.selected {
-            self.paint_tile_with_palette(context, index, SELECT_PALETTE);
+        match self.brush_rect {
+            Some(rect) => for (x, y) in rect.positions() {
+                self.block_at((x, y)).map(|index| {
+                    self.paint_tile_with_palette(context, index, SELECT_PALETTE)
+                });
+            },
+            None => if let Some(index) = self.selected {
+                self.paint_tile_with_palette(context, index, SELECT_PALETTE);
+            },
         }
     }
 
+    /// Block `index` drawn with `palette` whatever its attributes say, e.g. to highlight it
+    pub fn get_tile_pix_with_palette(&self, index: u8, palette: RgbPalette) -> Option<Pixbuf> {
+        self.blockset
+            .get(index)
+            .map(|block| pixbuf_from_block(&self.tiles, block, |_| palette))
+    }
+
     fn paint_tile_with_palette(&self, context: &cairo::Context, index: u8, palette: RgbPalette) {
-        let (x0, y0, x1, y1) = context.clip_extents();
-
-        let (sel_x, sel_y) = self.coords(index);
-        let sel_x1 = sel_x + BLOCK_SIZE as i32;
-        let sel_y1 = sel_y + BLOCK_SIZE as i32;
-
-        let within_left = sel_x >= x0 as i32 && sel_x <= x1 as i32;
-        let within_right = sel_x1 >= x0 as i32 && sel_x1 <= x1 as i32;
-        let within_top = sel_y >= y0 as i32 && sel_y <= y1 as i32;
-        let within_bottom = sel_y >= y0 as i32 && sel_y <= y1 as i32;
-
-        if (within_left || within_right) && (within_top || within_bottom) {
-            let x = max(sel_x, x0 as i32);
-            let y = max(sel_y, y0 as i32);
-
-            let width = min(sel_x1, x1 as i32) - x;
-            let height = min(sel_y1, y1 as i32) - y;
-
-            if width > 0 && height > 0 {
-                let subpix = self.pix_cache.new_subpixbuf(
-                    x as i32,
-                    y as i32,
-                    width as i32,
-                    height as i32,
-                );
-                let selected_subpix = change_palette(&subpix, self.palette, palette);
-                context.set_source_pixbuf(&selected_subpix, x as f64, y as f64);
-                context.paint();
-            }
+        let (x, y) = self.coords(index);
+        if let Some(pix) = self.get_tile_pix_with_palette(index, palette) {
+            set_source_pixbuf_sharp(context, &pix, x as f64, y as f64);
+            context.paint();
         }
     }
 
-    pub fn leave_notify(&mut self, el: &DrawingArea, _ev: &gdk::EventCrossing) {
+    /// Queue a redraw of the block at `(x, y)` unzoomed pixels
+    fn queue_draw_block(&self, (x, y): (i32, i32)) {
+        let (x, y, width, height) = self.zoomed_area(x, y, BLOCK_SIZE as i32, BLOCK_SIZE as i32);
+        self.widget.queue_draw_area(x, y, width, height);
+    }
+
+    pub fn leave_notify(&mut self, _: &DrawingArea, _ev: &gdk::EventCrossing) {
         if let Some(old_hovered) = self.hovered {
-            let (x, y) = self.coords(old_hovered);
-            el.queue_draw_area(x as i32, y as i32, BLOCK_SIZE as i32, BLOCK_SIZE as i32);
+            self.queue_draw_block(self.coords(old_hovered));
         }
         self.hovered = None;
     }
 
-    pub fn motion_notify(&mut self, el: &DrawingArea, ev: &gdk::EventMotion) {
-        let (lx, _) = get_event_pos(ev.get_position());
+    pub fn motion_notify(&mut self, _: &DrawingArea, ev: &gdk::EventMotion) {
+        let pos = self.get_event_pos(ev.get_position());
+
+        if let Some(start) = self.drag_start {
+            let rect = Rect::from_corners(start, pos);
+            if Some(rect) != self.brush_rect && (rect.width > 1 || rect.height > 1) {
+                self.set_brush_rect(rect);
+            }
+        }
+
+        let lx = match self.block_at(pos) {
+            Some(index) => index,
+            None => return,
+        };
 
         if let Some(old_hovered) = self.hovered {
             if lx != old_hovered {
-                let (x, y) = self.coords(old_hovered);
-                el.queue_draw_area(x as i32, y as i32, BLOCK_SIZE as i32, BLOCK_SIZE as i32);
+                self.queue_draw_block(self.coords(old_hovered));
 
                 self.hover_tile_at(lx);
-                let (x, y) = self.coords(lx);
-                el.queue_draw_area(x as i32, y as i32, BLOCK_SIZE as i32, BLOCK_SIZE as i32);
+                self.queue_draw_block(self.coords(lx));
             }
         } else {
             self.hover_tile_at(lx);
-            let (x, y) = self.coords(lx);
-            el.queue_draw_area(x as i32, y as i32, BLOCK_SIZE as i32, BLOCK_SIZE as i32);
+            self.queue_draw_block(self.coords(lx));
         }
     }
 
     pub fn button_press(&mut self, _: &DrawingArea, ev: &gdk::EventButton) {
-        let pos = get_event_pos(ev.get_position());
-        let (lx, _) = pos;
+        let pos = self.get_event_pos(ev.get_position());
+
+        if let Some(index) = self.block_at(pos) {
+            self.drag_start = Some(pos);
+            self.select_tile_at(index);
+        }
+    }
 
-        self.select_tile_at(lx);
+    pub fn button_release(&mut self, _: &DrawingArea, _: &gdk::EventButton) {
+        self.drag_start = None;
     }
 }
@@ -0,0 +1,428 @@
+use gdk::CursorType;
+
+use std::cell::RefCell;
+use std::cmp::{max, min};
+use std::fmt;
+
+use map::{line, Map, Rect, Region};
+use tileset::Tileset;
+
+/// What a tool can change while it handles a mouse event on the map
+pub struct ToolContext<'a> {
+    pub map: &'a mut Map,
+    pub tileset: &'a RefCell<Tileset>,
+    pub selection: &'a mut Option<Rect>,
+    /// Block left behind by tools that clear cells
+    pub background: u8,
+}
+
+/// Blocks and cells a tool wants shown over the map, without changing it
+#[derive(Debug, Default)]
+pub struct Preview {
+    pub blocks: Vec<(i32, i32, u8)>,
+    pub highlight: Option<Rect>,
+}
+
+impl Preview {
+    fn hovered(hovered: Option<(u8, u8)>) -> Self {
+        Preview {
+            blocks: Vec::new(),
+            highlight: hovered.map(|pos| Rect::from_corners(pos, pos)),
+        }
+    }
+
+    fn region(x: i32, y: i32, region: &Region) -> Self {
+        let mut blocks = Vec::new();
+        for ry in 0..region.height {
+            for rx in 0..region.width {
+                blocks.push((x + rx as i32, y + ry as i32, region.get(rx, ry)));
+            }
+        }
+        Preview {
+            blocks: blocks,
+            highlight: None,
+        }
+    }
+}
+
+/// An editing operation driven by the mouse. Positions are map cells, and
+/// `drag` and `release` only follow a `press` of the same tool.
+pub trait Tool: fmt::Debug {
+    fn name(&self) -> &'static str;
+
+    fn cursor(&self) -> CursorType;
+
+    /// Extra status bar text, e.g. the size of the shape being drawn
+    fn status(&self) -> Option<String> {
+        None
+    }
+
+    fn press(&mut self, _ctx: &mut ToolContext, _pos: (u8, u8)) {}
+
+    fn drag(&mut self, _ctx: &mut ToolContext, _pos: (u8, u8)) {}
+
+    fn release(&mut self, _ctx: &mut ToolContext) {}
+
+    /// The cursor moved to `pos` with no button pressed
+    fn hover(&mut self, _map: &Map, _pos: (u8, u8)) {}
+
+    /// The tool is being put away or the map changed under it: finish or
+    /// drop whatever it was doing
+    fn cancel(&mut self, _ctx: &mut ToolContext) {}
+
+    fn preview(&self, _tileset: &Tileset, hovered: Option<(u8, u8)>) -> Preview {
+        Preview::hovered(hovered)
+    }
+}
+
+/// Stamps the brush, tiling it along a stroke
+#[derive(Debug, Default)]
+pub struct Pencil {
+    origin: Option<(u8, u8)>,
+}
+
+impl Pencil {
+    /// Stamps of a stroke line up with its first one, so dragging a
+    /// multi-block brush tiles it instead of smearing it.
+    fn stamp(&mut self, ctx: &mut ToolContext, (x, y): (u8, u8)) {
+        let brush = ctx.tileset.borrow().brush();
+        if let Some(brush) = brush {
+            let (origin_x, origin_y) = self.origin.unwrap_or((x, y));
+            let snap = |pos: u8, origin: u8, size: u8| {
+                let offset = pos as i32 - origin as i32;
+                let size = size as i32;
+                origin as i32 + (offset - ((offset % size) + size) % size)
+            };
+
+            ctx.map.paste_region(
+                snap(x, origin_x, brush.width),
+                snap(y, origin_y, brush.height),
+                &brush,
+            );
+            self.origin = Some((origin_x, origin_y));
+        }
+    }
+}
+
+impl Tool for Pencil {
+    fn name(&self) -> &'static str {
+        "Pencil"
+    }
+
+    fn cursor(&self) -> CursorType {
+        CursorType::Pencil
+    }
+
+    fn press(&mut self, ctx: &mut ToolContext, pos: (u8, u8)) {
+        self.origin = None;
+        self.stamp(ctx, pos);
+    }
+
+    fn drag(&mut self, ctx: &mut ToolContext, pos: (u8, u8)) {
+        self.stamp(ctx, pos);
+    }
+
+    /* the whole stroke is a single undo step */
+    fn release(&mut self, ctx: &mut ToolContext) {
+        if self.origin.take().is_some() {
+            ctx.map.commit();
+        }
+    }
+
+    fn cancel(&mut self, ctx: &mut ToolContext) {
+        self.release(ctx);
+    }
+
+    fn preview(&self, tileset: &Tileset, hovered: Option<(u8, u8)>) -> Preview {
+        match (tileset.brush(), hovered) {
+            (Some(ref brush), Some((x, y))) if brush.width > 1 || brush.height > 1 => {
+                Preview::region(x as i32, y as i32, brush)
+            }
+            _ => Preview::hovered(hovered),
+        }
+    }
+}
+
+/// Picks the block under the cursor, or a dragged rectangle of blocks, as the brush
+#[derive(Debug, Default)]
+pub struct Eyedropper {
+    start: Option<(u8, u8)>,
+    picked: Option<Rect>,
+    under_cursor: Option<u8>,
+}
+
+impl Tool for Eyedropper {
+    fn name(&self) -> &'static str {
+        "Eyedropper"
+    }
+
+    fn cursor(&self) -> CursorType {
+        CursorType::Target
+    }
+
+    fn status(&self) -> Option<String> {
+        match self.picked {
+            Some(rect) => Some(format!("picking {}x{}", rect.width, rect.height)),
+            None => self.under_cursor.map(|block| format!("block {:#04x}", block)),
+        }
+    }
+
+    fn press(&mut self, _ctx: &mut ToolContext, pos: (u8, u8)) {
+        self.start = Some(pos);
+        self.picked = Some(Rect::from_corners(pos, pos));
+    }
+
+    fn drag(&mut self, _ctx: &mut ToolContext, pos: (u8, u8)) {
+        if let Some(start) = self.start {
+            self.picked = Some(Rect::from_corners(start, pos));
+        }
+    }
+
+    fn release(&mut self, ctx: &mut ToolContext) {
+        self.start = None;
+        if let Some(rect) = self.picked.take() {
+            let region = ctx.map.copy_region(rect);
+            ctx.tileset.borrow_mut().set_brush(region);
+        }
+    }
+
+    fn hover(&mut self, map: &Map, (x, y): (u8, u8)) {
+        self.under_cursor = map.get(x, y);
+    }
+
+    fn cancel(&mut self, _ctx: &mut ToolContext) {
+        self.start = None;
+        self.picked = None;
+    }
+
+    fn preview(&self, _tileset: &Tileset, hovered: Option<(u8, u8)>) -> Preview {
+        match self.picked {
+            Some(rect) => Preview {
+                blocks: Vec::new(),
+                highlight: Some(rect),
+            },
+            None => Preview::hovered(hovered),
+        }
+    }
+}
+
+/// Flood fills with the selected block, inside the selection if any
+#[derive(Debug, Default)]
+pub struct Fill;
+
+impl Tool for Fill {
+    fn name(&self) -> &'static str {
+        "Fill"
+    }
+
+    fn cursor(&self) -> CursorType {
+        CursorType::Spraycan
+    }
+
+    fn press(&mut self, ctx: &mut ToolContext, (x, y): (u8, u8)) {
+        let selected_block = ctx.tileset.borrow().selected;
+        if let Some(block) = selected_block {
+            ctx.map.flood_fill(x, y, block, *ctx.selection);
+            ctx.map.commit_as("Fill");
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Shape {
+    Rectangle,
+    FilledRectangle,
+    Line,
+}
+
+/// Draws a shape of the selected block from where the drag starts to where
+/// it ends, previewing it in the meantime
+#[derive(Debug)]
+pub struct ShapeTool {
+    shape: Shape,
+    drag: Option<((u8, u8), (u8, u8))>,
+}
+
+impl ShapeTool {
+    pub fn new(shape: Shape) -> Self {
+        ShapeTool {
+            shape: shape,
+            drag: None,
+        }
+    }
+
+    fn cells(&self, start: (u8, u8), end: (u8, u8)) -> Vec<(u8, u8)> {
+        match self.shape {
+            Shape::Rectangle => Rect::from_corners(start, end).outline(),
+            Shape::FilledRectangle => Rect::from_corners(start, end).positions(),
+            Shape::Line => line(start, end),
+        }
+    }
+}
+
+impl Tool for ShapeTool {
+    fn name(&self) -> &'static str {
+        match self.shape {
+            Shape::Rectangle => "Rectangle",
+            Shape::FilledRectangle => "Filled rectangle",
+            Shape::Line => "Line",
+        }
+    }
+
+    fn cursor(&self) -> CursorType {
+        CursorType::Tcross
+    }
+
+    fn status(&self) -> Option<String> {
+        self.drag.map(|(start, end)| {
+            let rect = Rect::from_corners(start, end);
+            format!("{}x{}", rect.width, rect.height)
+        })
+    }
+
+    fn press(&mut self, _ctx: &mut ToolContext, pos: (u8, u8)) {
+        self.drag = Some((pos, pos));
+    }
+
+    fn drag(&mut self, _ctx: &mut ToolContext, pos: (u8, u8)) {
+        if let Some((start, _)) = self.drag {
+            self.drag = Some((start, pos));
+        }
+    }
+
+    fn release(&mut self, ctx: &mut ToolContext) {
+        let selected_block = ctx.tileset.borrow().selected;
+        if let (Some((start, end)), Some(block)) = (self.drag.take(), selected_block) {
+            for (x, y) in self.cells(start, end) {
+                let _ = ctx.map.set(x, y, block);
+            }
+            ctx.map.commit_as(self.name());
+        }
+    }
+
+    fn cancel(&mut self, _ctx: &mut ToolContext) {
+        self.drag = None;
+    }
+
+    fn preview(&self, tileset: &Tileset, hovered: Option<(u8, u8)>) -> Preview {
+        match (self.drag, tileset.selected) {
+            (Some((start, end)), Some(block)) => Preview {
+                blocks: self.cells(start, end)
+                    .into_iter()
+                    .map(|(x, y)| (x as i32, y as i32, block))
+                    .collect(),
+                highlight: None,
+            },
+            _ => Preview::hovered(hovered),
+        }
+    }
+}
+
+/// Drags out the rectangular selection
+#[derive(Debug, Default)]
+pub struct Select {
+    start: Option<(u8, u8)>,
+}
+
+impl Tool for Select {
+    fn name(&self) -> &'static str {
+        "Select"
+    }
+
+    fn cursor(&self) -> CursorType {
+        CursorType::Crosshair
+    }
+
+    fn press(&mut self, ctx: &mut ToolContext, pos: (u8, u8)) {
+        self.start = Some(pos);
+        *ctx.selection = Some(Rect::from_corners(pos, pos));
+    }
+
+    fn drag(&mut self, ctx: &mut ToolContext, pos: (u8, u8)) {
+        if let Some(start) = self.start {
+            *ctx.selection = Some(Rect::from_corners(start, pos));
+        }
+    }
+
+    fn release(&mut self, _ctx: &mut ToolContext) {
+        self.start = None;
+    }
+
+    fn cancel(&mut self, _ctx: &mut ToolContext) {
+        self.start = None;
+    }
+}
+
+/// Drags the selected blocks elsewhere, leaving the background block behind,
+/// and moves the selection with them
+#[derive(Debug, Default)]
+pub struct MoveSelection {
+    from: Option<Rect>,
+    grab: (u8, u8),
+    blocks: Option<Region>,
+    target: Option<Rect>,
+}
+
+impl Tool for MoveSelection {
+    fn name(&self) -> &'static str {
+        "Move"
+    }
+
+    fn cursor(&self) -> CursorType {
+        CursorType::Fleur
+    }
+
+    fn status(&self) -> Option<String> {
+        self.target.map(|rect| format!("to ({}, {})", rect.x, rect.y))
+    }
+
+    fn press(&mut self, ctx: &mut ToolContext, (x, y): (u8, u8)) {
+        if let Some(rect) = ctx.selection.filter(|rect| rect.contains(x, y)) {
+            self.from = Some(rect);
+            self.grab = (x, y);
+            self.blocks = Some(ctx.map.copy_region(rect));
+            self.target = Some(rect);
+        }
+    }
+
+    /* the whole selection stays on the map */
+    fn drag(&mut self, ctx: &mut ToolContext, (x, y): (u8, u8)) {
+        if let Some(from) = self.from {
+            let place = |from: u8, grab: u8, to: u8, size: u8, map_size: u8| {
+                let pos = from as i32 + to as i32 - grab as i32;
+                max(0, min(pos, map_size as i32 - size as i32)) as u8
+            };
+            self.target = Some(Rect {
+                x: place(from.x, self.grab.0, x, from.width, ctx.map.width()),
+                y: place(from.y, self.grab.1, y, from.height, ctx.map.height()),
+                width: from.width,
+                height: from.height,
+            });
+        }
+    }
+
+    /* the move is a single undo step */
+    fn release(&mut self, ctx: &mut ToolContext) {
+        self.blocks = None;
+        if let (Some(from), Some(target)) = (self.from.take(), self.target.take()) {
+            if target != from {
+                ctx.map.move_rect(from, target.x, target.y, ctx.background);
+                ctx.map.commit_as(self.name());
+                *ctx.selection = Some(target);
+            }
+        }
+    }
+
+    fn cancel(&mut self, _ctx: &mut ToolContext) {
+        self.from = None;
+        self.blocks = None;
+        self.target = None;
+    }
+
+    fn preview(&self, _tileset: &Tileset, hovered: Option<(u8, u8)>) -> Preview {
+        match (self.blocks.as_ref(), self.target) {
+            (Some(blocks), Some(target)) => Preview::region(target.x as i32, target.y as i32, blocks),
+            _ => Preview::hovered(hovered),
+        }
+    }
+}